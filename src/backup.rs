@@ -1,10 +1,12 @@
-use crate::{constant, fileio, squire};
+use crate::{constant, fileio, mbdb, squire};
 
-use chrono::{DateTime, Local, Utc};
+use chrono::{DateTime, Utc};
 use plist::Value;
-use std::fs::read_dir;
-use std::path::Path;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::fs::{self, read_dir};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Function to list the available backups
 ///
@@ -18,33 +20,49 @@ pub fn list_backups(backups: &Vec<constant::Backup>) {
     let mut max_date = "Backup Date".len() + 3;
     let mut max_encrypted = "Encrypted".len() + 3;
     let mut max_size = "Size".len() + 3;
+    let mut max_state = "Status".len() + 3;
     let mut backup_info = Vec::new();
 
     for backup in backups {
+        let backup_date = backup.display_date();
+        let encrypted = backup.display_encrypted();
+        let backup_size = backup.display_size();
+        let state = backup.display_state();
+
         // Update max lengths dynamically
         max_serial = max_serial.max(backup.serial_number.len());
         max_device = max_device.max(backup.device_name.len());
         max_product = max_product.max(backup.product_name.len());
-        max_date = max_date.max(backup.backup_date.len());
-        max_encrypted = max_encrypted.max(backup.encrypted.len());
-        max_size = max_size.max(backup.backup_size.len());
+        max_date = max_date.max(backup_date.len());
+        max_encrypted = max_encrypted.max(encrypted.len());
+        max_size = max_size.max(backup_size.len());
+        max_state = max_state.max(state.len());
 
         backup_info.push((
             &backup.serial_number,
             &backup.device_name,
             &backup.product_name,
-            &backup.backup_date,
-            &backup.encrypted,
-            &backup.backup_size,
+            backup_date,
+            encrypted,
+            backup_size,
+            state,
         ));
     }
 
-    let table_width = max_serial + max_device + max_date + max_encrypted + 3 * 3; // 3 spaces between columns
+    let table_width = max_serial
+        + max_device
+        + max_product
+        + max_date
+        + max_encrypted
+        + max_size
+        + max_state
+        + 2 * 6; // 2 spaces between columns
     let title = "Available iOS Device Backups";
     println!("\n\n{0:^1$}", title, table_width);
 
     println!(
-        "{:-<width_serial$}  {:-<width_device$}  {:-<width_product$}  {:-<width_date$}  {:-<width_enc$}  {:-<width_size$}",
+        "{:-<width_serial$}  {:-<width_device$}  {:-<width_product$}  {:-<width_date$}  {:-<width_enc$}  {:-<width_size$}  {:-<width_state$}",
+        "",
         "",
         "",
         "",
@@ -56,27 +74,31 @@ pub fn list_backups(backups: &Vec<constant::Backup>) {
         width_product = max_product,
         width_date = max_date,
         width_enc = max_encrypted,
-        width_size = max_size
+        width_size = max_size,
+        width_state = max_state
     );
 
     println!(
-        "{:<width_serial$}  {:<width_device$}  {:<width_product$}  {:<width_date$}  {:<width_enc$}  {:<width_size$}",
+        "{:<width_serial$}  {:<width_device$}  {:<width_product$}  {:<width_date$}  {:<width_enc$}  {:<width_size$}  {:<width_state$}",
         "Serial Number",
         "Device",
         "Product",
         "Backup Date",
         "Encrypted",
         "Size",
+        "Status",
         width_serial = max_serial,
         width_device = max_device,
         width_product = max_product,
         width_date = max_date,
         width_enc = max_encrypted,
-        width_size = max_size
+        width_size = max_size,
+        width_state = max_state
     );
 
     println!(
-        "{:-<width_serial$}  {:-<width_device$}  {:-<width_product$}  {:-<width_date$}  {:-<width_enc$}  {:-<width_size$}",
+        "{:-<width_serial$}  {:-<width_device$}  {:-<width_product$}  {:-<width_date$}  {:-<width_enc$}  {:-<width_size$}  {:-<width_state$}",
+        "",
         "",
         "",
         "",
@@ -88,36 +110,140 @@ pub fn list_backups(backups: &Vec<constant::Backup>) {
         width_product = max_product,
         width_date = max_date,
         width_enc = max_encrypted,
-        width_size = max_size
+        width_size = max_size,
+        width_state = max_state
     );
 
-    for (serial_number, device_name, product_name, backup_date, encrypted, backup_size) in
+    for (serial_number, device_name, product_name, backup_date, encrypted, backup_size, state) in
         &backup_info
     {
         println!(
-            "{:<width_serial$}  {:<width_device$}  {:<width_product$}  {:<width_date$}  {:<width_enc$}  {:<width_size$}",
+            "{:<width_serial$}  {:<width_device$}  {:<width_product$}  {:<width_date$}  {:<width_enc$}  {:<width_size$}  {:<width_state$}",
             serial_number,
             device_name,
             product_name,
             backup_date,
             encrypted,
             backup_size,
+            state,
             width_serial = max_serial,
             width_device = max_device,
             width_product = max_product,
             width_date = max_date,
             width_enc = max_encrypted,
-            width_size = max_size
+            width_size = max_size,
+            width_state = max_state
         );
     }
 }
 
+/// Function to validate the completeness of a backup
+///
+/// Checks the `SnapshotState` in `Status.plist`, presence and openability of `Manifest.db` (or the legacy `Manifest.mbdb`),
+/// leftover `Snapshot` directories and the consistency of `Manifest.plist` with `Info.plist`
+///
+/// # Arguments
+///
+/// * `path` - The path to the backup directory
+/// * `info` - The `Info.plist` file as a `Value`
+/// * `udid` - The UDID of the device as stated in `Info.plist`
+/// * `encrypted` - Boolean flag to indicate if `Info.plist` states the backup is encrypted
+///
+/// # Returns
+///
+/// A tuple of the `BackupState` and the list of issues found
+pub fn validate(
+    path: &Path,
+    info: &Option<Value>,
+    udid: &str,
+    encrypted: bool,
+) -> (constant::BackupState, Vec<String>) {
+    let mut in_progress = Vec::new();
+    let mut corrupt = Vec::new();
+
+    if info.is_none() {
+        corrupt.push("Info.plist is unreadable".to_string());
+    }
+
+    match Value::from_file(path.join("Status.plist")) {
+        Ok(status) => {
+            let snapshot_state = fileio::get_plist_key(&Some(status), "SnapshotState", "");
+            if snapshot_state != "finished" {
+                in_progress.push(format!("SnapshotState is '{}'", snapshot_state));
+            }
+        }
+        Err(err) => corrupt.push(format!("Status.plist is unreadable: {}", err)),
+    }
+
+    if path.join("Snapshot").is_dir() {
+        in_progress.push("Leftover 'Snapshot' directory found".to_string());
+    }
+
+    let manifest_db = path.join("Manifest.db");
+    let manifest_mbdb = path.join("Manifest.mbdb");
+    if manifest_db.is_file() {
+        // Encrypted backups have an encrypted Manifest.db, which cannot be opened without the password
+        if !encrypted {
+            let result =
+                Connection::open_with_flags(&manifest_db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                    .and_then(|conn| {
+                        conn.query_row("SELECT COUNT(*) FROM Files", [], |row| row.get::<_, i64>(0))
+                    });
+            if let Err(err) = result {
+                corrupt.push(format!("Manifest.db cannot be opened: {}", err));
+            }
+        }
+    } else if manifest_mbdb.is_file() {
+        if let Err(err) = mbdb::parse(&manifest_mbdb) {
+            corrupt.push(format!("Manifest.mbdb cannot be parsed: {}", err));
+        }
+    } else {
+        corrupt.push("Manifest.db is missing".to_string());
+    }
+
+    match Value::from_file(path.join("Manifest.plist")) {
+        Ok(manifest) => {
+            let manifest = Some(manifest);
+            let manifest_encrypted =
+                fileio::get_plist_bool(&manifest, "IsEncrypted").unwrap_or(false);
+            if manifest_encrypted != encrypted {
+                corrupt.push(
+                    "Encryption status in Manifest.plist and Info.plist do not match".to_string(),
+                );
+            }
+            let manifest_udid = manifest
+                .as_ref()
+                .and_then(|v| v.as_dictionary()?.get("Lockdown"))
+                .and_then(|v| v.as_dictionary()?.get("UniqueDeviceID"))
+                .and_then(Value::as_string);
+            if let Some(manifest_udid) = manifest_udid {
+                if !udid.is_empty() && !manifest_udid.eq_ignore_ascii_case(udid) {
+                    corrupt.push(
+                        "Device UDID in Manifest.plist and Info.plist do not match".to_string(),
+                    );
+                }
+            }
+        }
+        Err(err) => corrupt.push(format!("Manifest.plist is unreadable: {}", err)),
+    }
+
+    let state = if !corrupt.is_empty() {
+        constant::BackupState::Corrupt
+    } else if !in_progress.is_empty() {
+        constant::BackupState::InProgress
+    } else {
+        constant::BackupState::Complete
+    };
+    corrupt.extend(in_progress);
+    (state, corrupt)
+}
+
 /// Function to get the available backups
 ///
 /// # Arguments
 ///
 /// * `backup_root` - The path to the backup root directory
-/// * `serial_filters` - The serial number(s) to filter the backups
+/// * `targets` - Serial numbers, UDIDs or backup directory names to filter the backups
 /// * `no_filter` - Boolean flag to include all backups
 ///
 /// # Returns
@@ -125,7 +251,7 @@ pub fn list_backups(backups: &Vec<constant::Backup>) {
 /// A vector of `Backup` structs
 pub fn get_backups(
     backup_root: &Path,
-    serial_filters: &[String],
+    targets: &[String],
     no_filter: bool,
 ) -> Vec<constant::Backup> {
     let mut backups = Vec::new();
@@ -141,50 +267,42 @@ pub fn get_backups(
                     let product_name =
                         fileio::get_plist_key(&info, "Product Name", "Unknown Product");
 
-                    let date = info
+                    let udid = fileio::get_plist_key(&info, "Unique Identifier", "");
+                    let product_type = fileio::get_plist_key(&info, "Product Type", "");
+                    let ios_version = fileio::get_plist_key(&info, "Product Version", "");
+                    let build_version = fileio::get_plist_key(&info, "Build Version", "");
+                    let phone_number = fileio::get_plist_key(&info, "Phone Number", "");
+                    let backup_date = info
                         .as_ref()
                         .and_then(|v| v.as_dictionary()?.get("Last Backup Date"))
-                        .and_then(Value::as_date);
-                    let datetime = date.map_or("".to_string(), |date| {
-                        let system_time: SystemTime = date.into();
-                        let datetime_utc: DateTime<Utc> = system_time.into();
-                        let datetime_local = datetime_utc.with_timezone(&Local);
-                        datetime_local.format("%b %d, %Y %I:%M %p").to_string()
-                    });
-                    let seconds = date.map_or(0, |date| {
-                        let system_time: SystemTime = date.into();
-                        let duration_since_epoch = system_time
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or(Duration::new(0, 0));
-                        duration_since_epoch.as_secs()
-                    });
-                    let backup_date = format!(
-                        "{} ({} ago)",
-                        datetime,
-                        squire::convert_seconds((squire::get_epoch() - seconds) as i64, 1)
-                    );
-
-                    let encrypted = info
-                        .as_ref()
-                        .and_then(|v| match v.as_dictionary() {
-                            Some(dict) => dict.get("IsEncrypted"),
-                            None => None,
-                        })
-                        .map_or("No".to_string(), |v| match v.as_boolean() {
-                            Some(true) => "Yes".to_string(),
-                            _ => "No".to_string(),
-                        });
-                    let backup_size_raw = squire::get_size(&path);
-                    let backup_size = squire::size_converter(backup_size_raw);
-                    if no_filter || serial_filters.contains(&serial_number) {
+                        .and_then(Value::as_date)
+                        .map(|date| DateTime::<Utc>::from(SystemTime::from(date)));
+                    let encrypted = fileio::get_plist_bool(&info, "IsEncrypted").unwrap_or(false);
+                    let backup_size = None;
+                    let selected = targets
+                        .iter()
+                        .any(|target| is_target(&path, &serial_number, &udid, target));
+                    if no_filter || selected {
+                        // Only the selected backups are validated, as it opens the manifest
+                        let (state, issues) = validate(&path, &info, &udid, encrypted);
+                        let format = constant::ManifestFormat::detect(&path);
                         backups.push(constant::Backup {
                             path,
                             serial_number,
                             device_name,
                             product_name,
+                            product_type,
+                            udid,
+                            ios_version,
+                            build_version,
+                            phone_number,
                             backup_date,
                             backup_size,
                             encrypted,
+                            state,
+                            issues,
+                            format,
+                            keys: None,
                         });
                     }
                 }
@@ -193,3 +311,157 @@ pub fn get_backups(
     }
     backups
 }
+
+/// Function to find a backup by its serial number or UDID
+///
+/// # Arguments
+///
+/// * `backups` - A slice of `Backup` structs
+/// * `target` - Serial number, UDID or the backup directory name
+///
+/// # Returns
+///
+/// An `Option` containing the matching `Backup`
+pub fn find_backup<'a>(
+    backups: &'a [constant::Backup],
+    target: &str,
+) -> Option<&'a constant::Backup> {
+    backups
+        .iter()
+        .find(|backup| is_target(&backup.path, &backup.serial_number, &backup.udid, target))
+}
+
+/// Function to check if a backup is identified by a target
+///
+/// # Arguments
+///
+/// * `path` - The path to the backup directory
+/// * `serial_number` - The serial number of the device
+/// * `udid` - The UDID of the device
+/// * `target` - Serial number, UDID or the backup directory name
+///
+/// # Returns
+///
+/// A `bool` indicating if the target matches the backup
+fn is_target(path: &Path, serial_number: &str, udid: &str, target: &str) -> bool {
+    serial_number == target
+        || udid.eq_ignore_ascii_case(target)
+        || path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().eq_ignore_ascii_case(target))
+}
+
+/// Function to get the path to the backup size cache
+///
+/// # Returns
+///
+/// An `Option` containing the path to the cache file
+fn size_cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("ios").join("backup_sizes"))
+}
+
+/// Function to get the latest modified time of a backup
+///
+/// Looks at the backup directory and the files that are rewritten on every backup.
+///
+/// # Arguments
+///
+/// * `path` - The path to the backup directory
+///
+/// # Returns
+///
+/// A `u64` containing the modified time in seconds since epoch
+fn backup_mtime(path: &Path) -> u64 {
+    [
+        path.to_path_buf(),
+        path.join("Manifest.db"),
+        path.join("Manifest.mbdb"),
+        path.join("Status.plist"),
+        path.join("Info.plist"),
+    ]
+    .iter()
+    .filter_map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+    .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+    .map(|duration| duration.as_secs())
+    .max()
+    .unwrap_or_default()
+}
+
+/// Function to load the backup size cache
+///
+/// Each line in the cache is stored as `<mtime>\t<size>\t<path>`
+///
+/// # Returns
+///
+/// A `HashMap` with the backup path as the key and a tuple of mtime and size as the value
+fn load_size_cache() -> HashMap<PathBuf, (u64, u64)> {
+    let mut cache = HashMap::new();
+    let content = match size_cache_path().map(fs::read_to_string) {
+        Some(Ok(content)) => content,
+        _ => return cache,
+    };
+    for line in content.lines() {
+        let mut parts = line.splitn(3, '\t');
+        if let (Some(mtime), Some(size), Some(path)) = (parts.next(), parts.next(), parts.next()) {
+            if let (Ok(mtime), Ok(size)) = (mtime.parse(), size.parse()) {
+                cache.insert(PathBuf::from(path), (mtime, size));
+            }
+        }
+    }
+    cache
+}
+
+/// Function to store the backup size cache
+///
+/// # Arguments
+///
+/// * `cache` - A `HashMap` with the backup path as the key and a tuple of mtime and size as the value
+fn save_size_cache(cache: &HashMap<PathBuf, (u64, u64)>) {
+    let Some(cache_path) = size_cache_path() else {
+        return;
+    };
+    let content: String = cache
+        .iter()
+        .map(|(path, (mtime, size))| format!("{}\t{}\t{}\n", mtime, size, path.display()))
+        .collect();
+    let result = cache_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&cache_path, content));
+    if let Err(err) = result {
+        log::warn!(
+            "Failed to store size cache '{}': {}",
+            cache_path.display(),
+            err
+        );
+    }
+}
+
+/// Function to compute the size of the backups
+///
+/// Sizes are cached against the modified time of each backup, so unchanged backups are not walked again.
+///
+/// # Arguments
+///
+/// * `backups` - A mutable slice of `Backup` structs
+/// * `workers` - Number of threads to use while walking each backup
+pub fn compute_sizes(backups: &mut [constant::Backup], workers: usize) {
+    let mut cache = load_size_cache();
+    for backup in backups.iter_mut() {
+        let mtime = backup_mtime(&backup.path);
+        let size = match cache.get(&backup.path) {
+            Some((cached_mtime, size)) if *cached_mtime == mtime => {
+                log::debug!("Using cached size for '{}'", backup.path.display());
+                *size
+            }
+            _ => {
+                log::debug!("Computing size for '{}'", backup.path.display());
+                let size = squire::get_size_parallel(&backup.path, workers);
+                cache.insert(backup.path.clone(), (mtime, size));
+                size
+            }
+        };
+        backup.backup_size = Some(size);
+    }
+    save_size_cache(&cache);
+}
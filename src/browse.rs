@@ -0,0 +1,245 @@
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::squire;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+
+/// Struct to represent a file or directory while browsing a backup
+///
+/// Directories that are not listed in the manifest are synthesized from the paths of their children.
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    pub name: String,
+    pub entry: Option<ManifestEntry>,
+    pub is_dir: bool,
+    pub children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    /// Returns the size of the file, or `0` for directories
+    pub fn size(&self) -> u64 {
+        self.entry.as_ref().map_or(0, ManifestEntry::size)
+    }
+
+    /// Inserts an entry at the given path components, creating the intermediate directories
+    fn insert(&mut self, components: &[&str], entry: ManifestEntry, depth: usize) {
+        let Some((first, rest)) = components.split_first() else {
+            return;
+        };
+        let child = self
+            .children
+            .entry(first.to_string())
+            .or_insert_with(|| Node {
+                name: first.to_string(),
+                ..Node::default()
+            });
+        if rest.is_empty() {
+            child.is_dir = entry.is_directory();
+            child.entry = Some(entry);
+        } else {
+            child.is_dir = true;
+            if depth > 1 {
+                child.insert(rest, entry, depth - 1);
+            }
+        }
+    }
+}
+
+/// Function to list the contents of a backup like a filesystem
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest of the backup
+/// * `domain` - The domain to list, or `None` to list the domains
+/// * `relative_path` - The directory within the domain to list
+/// * `depth` - Number of levels to descend into
+///
+/// # Returns
+///
+/// * `Ok` - A `Node` for the listed directory, with its children populated up to `depth`
+/// * `Err` - If the manifest cannot be read
+pub fn list_dir(
+    manifest: &Manifest,
+    domain: Option<&str>,
+    relative_path: &str,
+    depth: usize,
+) -> rusqlite::Result<Node> {
+    let Some(domain) = domain else {
+        let mut root = Node {
+            is_dir: true,
+            ..Node::default()
+        };
+        for domain in manifest.domains()? {
+            let node = if depth > 1 {
+                Node {
+                    name: domain.clone(),
+                    ..list_dir(manifest, Some(&domain), "", depth - 1)?
+                }
+            } else {
+                Node {
+                    name: domain.clone(),
+                    is_dir: true,
+                    ..Node::default()
+                }
+            };
+            root.children.insert(domain, node);
+        }
+        return Ok(root);
+    };
+
+    let relative_path = relative_path.trim_matches('/');
+    let prefix = if relative_path.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", relative_path)
+    };
+    let mut root = Node {
+        name: relative_path
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        is_dir: true,
+        ..Node::default()
+    };
+    for entry in manifest.entries().domain(domain).path_prefix(&prefix) {
+        let entry = entry?;
+        let remainder = entry.relative_path[prefix.len()..].to_string();
+        let components: Vec<&str> = remainder.split('/').filter(|c| !c.is_empty()).collect();
+        root.insert(&components, entry, depth);
+    }
+    if root.children.is_empty() && !relative_path.is_empty() {
+        // The path may point to a file rather than a directory
+        if let Some(entry) = manifest.find(domain, relative_path)? {
+            if !entry.is_directory() {
+                root.is_dir = false;
+                root.children.insert(
+                    root.name.clone(),
+                    Node {
+                        name: root.name.clone(),
+                        entry: Some(entry),
+                        ..Node::default()
+                    },
+                );
+            }
+        }
+    }
+    Ok(root)
+}
+
+/// Function to convert the file mode into the `ls -l` representation
+///
+/// # Arguments
+///
+/// * `mode` - The file mode
+///
+/// # Returns
+///
+/// A `String` such as `-rw-r--r--`
+fn mode_string(mode: u32) -> String {
+    let kind = match mode & 0xF000 {
+        0x4000 => 'd',
+        0xA000 => 'l',
+        _ => '-',
+    };
+    let permissions: String = (0..9)
+        .map(|bit| {
+            if mode & (1 << (8 - bit)) == 0 {
+                '-'
+            } else {
+                ['r', 'w', 'x'][bit % 3]
+            }
+        })
+        .collect();
+    format!("{}{}", kind, permissions)
+}
+
+/// Function to format a single node for the long listing
+///
+/// # Arguments
+///
+/// * `node` - The node to format
+///
+/// # Returns
+///
+/// A `String` with the mode, size and modified time of the node
+fn long_format(node: &Node) -> String {
+    let metadata = node
+        .entry
+        .as_ref()
+        .and_then(|entry| entry.metadata.as_ref());
+    let mode = match metadata {
+        Some(meta) => mode_string(meta.mode),
+        None if node.is_dir => "d?????????".to_string(),
+        None => "??????????".to_string(),
+    };
+    let size = if node.is_dir {
+        "-".to_string()
+    } else {
+        squire::size_converter(node.size())
+    };
+    let modified = metadata
+        .and_then(|meta| DateTime::from_timestamp(meta.last_modified, 0))
+        .map_or("-".to_string(), |date| {
+            date.with_timezone(&Local)
+                .format("%b %d, %Y %H:%M")
+                .to_string()
+        });
+    format!("{}  {:>10}  {:<18}", mode, size, modified)
+}
+
+/// Function to format the name of a node, marking directories with a trailing slash
+fn display_name(node: &Node) -> String {
+    match node
+        .entry
+        .as_ref()
+        .and_then(|e| e.metadata.as_ref()?.target.as_ref())
+    {
+        Some(target) => format!("{} -> {}", node.name, target),
+        None if node.is_dir => format!("{}/", node.name),
+        None => node.name.clone(),
+    }
+}
+
+/// Function to print the children of a directory
+///
+/// # Arguments
+///
+/// * `node` - The directory to print
+/// * `long` - Boolean flag to include the mode, size and modified time
+pub fn print_list(node: &Node, long: bool) {
+    for child in node.children.values() {
+        if long {
+            println!("{}  {}", long_format(child), display_name(child));
+        } else {
+            println!("{}", display_name(child));
+        }
+    }
+}
+
+/// Function to print a directory as a tree
+///
+/// # Arguments
+///
+/// * `node` - The directory to print
+/// * `long` - Boolean flag to include the mode, size and modified time
+/// * `indent` - The prefix to print before each child
+pub fn print_tree(node: &Node, long: bool, indent: &str) {
+    let count = node.children.len();
+    for (index, child) in node.children.values().enumerate() {
+        let last = index + 1 == count;
+        let branch = if last { "└── " } else { "├── " };
+        if long {
+            println!(
+                "{}  {}{}{}",
+                long_format(child),
+                indent,
+                branch,
+                display_name(child)
+            );
+        } else {
+            println!("{}{}{}", indent, branch, display_name(child));
+        }
+        let nested = format!("{}{}", indent, if last { "    " } else { "│   " });
+        print_tree(child, long, &nested);
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Struct to cancel an extraction from another thread
+///
+/// Clones share the same state, so a clone can be kept by the caller while the extraction holds another.
+/// The workers check it between files and while copying large files.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl CancellationToken {
+    /// Function to create a new cancellation token
+    ///
+    /// # Returns
+    ///
+    /// A `CancellationToken` that is not cancelled
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Function to create a token that is cancelled along with this one, but can also be cancelled on its own
+    ///
+    /// # Returns
+    ///
+    /// A child `CancellationToken`
+    pub fn child(&self) -> CancellationToken {
+        CancellationToken {
+            cancelled: Arc::new(AtomicBool::new(self.is_cancelled())),
+            parent: Some(Arc::clone(&self.cancelled)),
+        }
+    }
+
+    /// Function to request the cancellation of the extraction
+    ///
+    /// # Returns
+    ///
+    /// A `bool` indicating if the token was already cancelled
+    pub fn cancel(&self) -> bool {
+        self.cancelled.swap(true, Ordering::SeqCst)
+    }
+
+    /// Function to check if the cancellation was requested
+    ///
+    /// # Returns
+    ///
+    /// A `bool` indicating if the token is cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.load(Ordering::SeqCst))
+    }
+
+    /// Function to get an error if the cancellation was requested
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - If the token is not cancelled
+    /// * `Err` - An `Interrupted` error if the token is cancelled
+    pub fn check(&self) -> std::io::Result<()> {
+        if self.is_cancelled() {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "Extraction cancelled",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
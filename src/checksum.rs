@@ -0,0 +1,573 @@
+use crate::journal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Name of the `sha256sum` compatible manifest stored in the output directory of each device
+pub const SHA256SUMS_NAME: &str = "SHA256SUMS";
+/// Name of the `b3sum` compatible manifest, written when the files are also hashed with BLAKE3
+pub const B3SUMS_NAME: &str = "B3SUMS";
+/// Name of the JSON index with the size and hashes of every extracted file
+pub const INDEX_NAME: &str = "checksums.json";
+/// Names of all the checksum files, which are not part of the extracted files
+pub const MANIFEST_NAMES: [&str; 3] = [SHA256SUMS_NAME, B3SUMS_NAME, INDEX_NAME];
+
+/// Size of the buffer used to hash files
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// Enum for the hash algorithms the extracted files can be recorded with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// SHA-256 only
+    Sha256,
+    /// SHA-256 and BLAKE3
+    Blake3,
+}
+
+/// Struct to store the hashes of a file as hex strings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Digests {
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+/// Struct to hash data with SHA-256, and optionally BLAKE3, in a single pass
+pub struct Hasher {
+    sha256: Sha256,
+    blake3: Option<blake3::Hasher>,
+}
+
+/// Function to format bytes as a lowercase hex string
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes to format
+///
+/// # Returns
+///
+/// A `String` with two hex digits per byte
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Hasher {
+    /// Function to create a hasher
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` - The algorithm to hash with, or `None` for SHA-256 only
+    ///
+    /// # Returns
+    ///
+    /// A `Hasher` struct
+    pub fn new(algorithm: Option<Algorithm>) -> Hasher {
+        Hasher {
+            sha256: Sha256::new(),
+            blake3: (algorithm == Some(Algorithm::Blake3)).then(blake3::Hasher::new),
+        }
+    }
+
+    /// Function to feed data to the hasher
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The next chunk of data
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        if let Some(blake3) = &mut self.blake3 {
+            blake3.update(data);
+        }
+    }
+
+    /// Function to finish hashing
+    ///
+    /// # Returns
+    ///
+    /// The `Digests` of the data
+    pub fn finalize(self) -> Digests {
+        Digests {
+            sha256: hex(&self.sha256.finalize()),
+            blake3: self
+                .blake3
+                .map(|blake3| blake3.finalize().to_hex().to_string()),
+        }
+    }
+}
+
+/// Function to hash a file on disk
+///
+/// # Arguments
+///
+/// * `path` - The path to the file
+/// * `algorithm` - The algorithm to hash with, or `None` for SHA-256 only
+///
+/// # Returns
+///
+/// * `Ok` - The `Digests` of the file
+/// * `Err` - If the file cannot be read
+pub fn hash_file(path: &Path, algorithm: Option<Algorithm>) -> io::Result<Digests> {
+    let mut file = File::open(path)?;
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+/// Struct to store an extracted file in the checksum index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Path of the file relative to the output directory of the device, with `/` separators
+    pub path: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub digests: Digests,
+    pub file_id: String,
+    pub domain: String,
+    pub relative_path: String,
+}
+
+/// Struct to store the checksum index of the output directory of a device
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    pub serial_number: String,
+    pub updated_at: String,
+    pub files: Vec<IndexEntry>,
+}
+
+/// Function to get the path of a file relative to the output directory, as written to the manifests
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+/// * `path` - The path to the file
+///
+/// # Returns
+///
+/// An `Option` with the relative path using `/` separators, if the file is within the output directory
+pub fn relative_name(output_path: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(output_path).ok()?;
+    let parts: Vec<String> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some(parts.join("/"))
+}
+
+/// Function to read the checksum index of an output directory
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+///
+/// # Returns
+///
+/// * `Ok` - The `Index`, which is empty if the directory has none
+/// * `Err` - If the index cannot be read or parsed
+pub fn read_index(output_path: &Path) -> Result<Index, Box<dyn std::error::Error>> {
+    let path = output_path.join(INDEX_NAME);
+    if !path.exists() {
+        return Ok(Index::default());
+    }
+    let index = serde_json::from_reader(io::BufReader::new(File::open(&path)?))
+        .map_err(|err| format!("Failed to parse '{}': {}", path.display(), err))?;
+    Ok(index)
+}
+
+/// Function to write a file next to its final path and rename it once complete
+///
+/// # Arguments
+///
+/// * `path` - The path of the file
+/// * `content` - The content of the file
+///
+/// # Returns
+///
+/// * `Ok` - If the file was written
+/// * `Err` - If the function encounters an error
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.ios-part", filename));
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Function to add the files extracted in this run to the checksum index and rewrite the manifests
+///
+/// Files recorded by earlier runs are kept as long as they are still in the output directory,
+/// so the manifests cover the whole tree after a `--resume` or `--sync`.
+/// The ones copied again without being hashed are re-hashed, as their recorded hashes may be out of date.
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+/// * `serial_number` - The serial number of the device
+/// * `entries` - The files extracted and hashed in this run
+/// * `rewritten` - The paths of the files extracted in this run without being hashed
+///
+/// # Returns
+///
+/// * `Ok` - The number of files in the index
+/// * `Err` - If the index cannot be read or written
+pub fn update_index(
+    output_path: &Path,
+    serial_number: &str,
+    entries: Vec<IndexEntry>,
+    rewritten: &[String],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let index = read_index(output_path)?;
+    let rewritten: HashSet<&str> = rewritten.iter().map(String::as_str).collect();
+    let mut files = BTreeMap::new();
+    for mut entry in index.files {
+        let path = output_path.join(&entry.path);
+        if !path.is_file() {
+            continue;
+        }
+        if rewritten.contains(entry.path.as_str()) {
+            let algorithm = entry.digests.blake3.as_ref().map(|_| Algorithm::Blake3);
+            match hash_file(&path, algorithm).and_then(|digests| Ok((digests, path.metadata()?))) {
+                Ok((digests, metadata)) => {
+                    entry.digests = digests;
+                    entry.size = metadata.len();
+                }
+                Err(err) => {
+                    log::warn!("Failed to hash '{}': {}", path.display(), err);
+                    continue;
+                }
+            }
+        }
+        files.insert(entry.path.clone(), entry);
+    }
+    for entry in entries {
+        files.insert(entry.path.clone(), entry);
+    }
+    let index = Index {
+        serial_number: serial_number.to_string(),
+        updated_at: chrono::Local::now().to_rfc3339(),
+        files: files.into_values().collect(),
+    };
+
+    let mut sha256sums = String::new();
+    let mut b3sums = String::new();
+    for entry in &index.files {
+        sha256sums.push_str(&format!("{}  {}\n", entry.digests.sha256, entry.path));
+        if let Some(blake3) = &entry.digests.blake3 {
+            b3sums.push_str(&format!("{}  {}\n", blake3, entry.path));
+        }
+    }
+    write_atomic(&output_path.join(SHA256SUMS_NAME), sha256sums.as_bytes())?;
+    let b3sums_path = output_path.join(B3SUMS_NAME);
+    if !b3sums.is_empty() {
+        write_atomic(&b3sums_path, b3sums.as_bytes())?;
+    } else if b3sums_path.exists() {
+        // Left over from a run hashed with BLAKE3, none of the files it lists are recorded with it anymore
+        std::fs::remove_file(&b3sums_path)?;
+    }
+    write_atomic(
+        &output_path.join(INDEX_NAME),
+        serde_json::to_string_pretty(&index)?.as_bytes(),
+    )?;
+    Ok(index.files.len())
+}
+
+/// Struct to store the outcome of verifying an output directory against its checksum index
+#[derive(Debug, Default)]
+pub struct OutputReport {
+    pub checked: usize,
+    pub mismatched: Vec<String>,
+    pub missing: Vec<String>,
+    pub unreadable: Vec<(String, String)>,
+    pub untracked: Vec<String>,
+}
+
+impl OutputReport {
+    /// Returns `true` if every file matches the index
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty()
+            && self.missing.is_empty()
+            && self.unreadable.is_empty()
+            && self.untracked.is_empty()
+    }
+}
+
+/// Function to list the files in the output directory, except the journal and the checksum files
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+///
+/// # Returns
+///
+/// A vector with the path of each file relative to the output directory
+fn tracked_files(output_path: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut pending = vec![output_path.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        let entries = match std::fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("Failed to read '{}': {}", directory.display(), err);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if path.is_dir() {
+                pending.push(path);
+            } else if name.starts_with(journal::JOURNAL_NAME)
+                || (directory == output_path && MANIFEST_NAMES.contains(&name.as_str()))
+            {
+                continue;
+            } else if let Some(relative) = relative_name(output_path, &path) {
+                files.push(relative);
+            }
+        }
+    }
+    files
+}
+
+/// Function to re-hash an output directory and compare it against its checksum index
+///
+/// Files are hashed with the same algorithms they were recorded with,
+/// and files that are not in the index are reported as untracked.
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+/// * `workers` - Number of files to hash at the same time
+///
+/// # Returns
+///
+/// * `Ok` - An `OutputReport` with the files that do not match
+/// * `Err` - If the directory has no index, or it cannot be read
+pub fn verify_output(
+    output_path: &Path,
+    workers: usize,
+) -> Result<OutputReport, Box<dyn std::error::Error>> {
+    if !output_path.join(INDEX_NAME).exists() {
+        return Err(format!(
+            "No '{}' found in '{}', extract with '--checksum' first",
+            INDEX_NAME,
+            output_path.display()
+        )
+        .into());
+    }
+    let index = read_index(output_path)?;
+    let report = Mutex::new(OutputReport::default());
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| {
+                while let Some(entry) = index.files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let algorithm = entry.digests.blake3.as_ref().map(|_| Algorithm::Blake3);
+                    let result = hash_file(&output_path.join(&entry.path), algorithm);
+                    let mut report = report.lock().unwrap();
+                    report.checked += 1;
+                    match result {
+                        Ok(digests) if digests == entry.digests => {}
+                        Ok(_) => report.mismatched.push(entry.path.clone()),
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                            report.missing.push(entry.path.clone())
+                        }
+                        Err(err) => report
+                            .unreadable
+                            .push((entry.path.clone(), err.to_string())),
+                    }
+                }
+            });
+        }
+    });
+    let mut report = report.into_inner().unwrap();
+    let indexed: HashSet<&str> = index
+        .files
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect();
+    report.untracked = tracked_files(output_path)
+        .into_iter()
+        .filter(|path| !indexed.contains(path.as_str()))
+        .collect();
+    report.mismatched.sort();
+    report.missing.sort();
+    report.unreadable.sort();
+    report.untracked.sort();
+    Ok(report)
+}
+
+/// Function to find the output directories that have a checksum index
+///
+/// # Arguments
+///
+/// * `path` - An output directory of a device, or the directory that holds them
+///
+/// # Returns
+///
+/// A vector with the output directories to be verified
+pub fn output_dirs(path: &Path) -> Vec<PathBuf> {
+    if path.join(INDEX_NAME).exists() {
+        return vec![path.to_path_buf()];
+    }
+    let mut directories: Vec<PathBuf> = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.join(INDEX_NAME).exists())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    directories.sort();
+    directories
+}
+
+/// Function to print the verification report of an output directory
+///
+/// # Arguments
+///
+/// * `report` - The verification report of an output directory
+pub fn print_report(report: &OutputReport) {
+    for path in &report.mismatched {
+        println!("MODIFIED    {}", path);
+    }
+    for path in &report.missing {
+        println!("MISSING     {}", path);
+    }
+    for (path, err) in &report.unreadable {
+        println!("UNREADABLE  {} ({})", path, err);
+    }
+    for path in &report.untracked {
+        println!("UNTRACKED   {}", path);
+    }
+    println!();
+    println!("{:<20} {}", "Files checked", report.checked);
+    println!("{:<20} {}", "Modified", report.mismatched.len());
+    println!("{:<20} {}", "Missing", report.missing.len());
+    println!("{:<20} {}", "Unreadable", report.unreadable.len());
+    println!("{:<20} {}", "Untracked", report.untracked.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Function to write a file into the output directory and describe it for the index
+    fn extracted(
+        output_path: &Path,
+        path: &str,
+        content: &[u8],
+        algorithm: Algorithm,
+    ) -> IndexEntry {
+        let file_path = output_path.join(path);
+        std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        std::fs::write(&file_path, content).unwrap();
+        IndexEntry {
+            path: path.to_string(),
+            size: content.len() as u64,
+            digests: hash_file(&file_path, Some(algorithm)).unwrap(),
+            file_id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            domain: "CameraRollDomain".to_string(),
+            relative_path: format!("Media/{}", path),
+        }
+    }
+
+    #[test]
+    fn sha256sums_round_trip_through_verify_output() {
+        let root = tempfile::tempdir().unwrap();
+        let output_path = root.path();
+        let entries = vec![
+            extracted(output_path, "DCIM/IMG_0001.JPG", b"abc", Algorithm::Sha256),
+            extracted(
+                output_path,
+                "DCIM/IMG_0002.JPG",
+                b"second",
+                Algorithm::Sha256,
+            ),
+        ];
+        assert_eq!(
+            update_index(output_path, "F2LXXXXXX01", entries, &[]).unwrap(),
+            2
+        );
+        let sha256sums = std::fs::read_to_string(output_path.join(SHA256SUMS_NAME)).unwrap();
+        assert!(sha256sums.starts_with(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  DCIM/IMG_0001.JPG\n"
+        ));
+        assert_eq!(sha256sums.lines().count(), 2);
+        assert!(!output_path.join(B3SUMS_NAME).exists());
+        std::fs::write(output_path.join(journal::JOURNAL_NAME), b"journal").unwrap();
+
+        let report = verify_output(output_path, 2).unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.checked, 2);
+
+        std::fs::write(output_path.join("DCIM/IMG_0001.JPG"), b"abd").unwrap();
+        std::fs::remove_file(output_path.join("DCIM/IMG_0002.JPG")).unwrap();
+        std::fs::write(output_path.join("DCIM/IMG_0003.JPG"), b"untracked").unwrap();
+        let report = verify_output(output_path, 1).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.mismatched, ["DCIM/IMG_0001.JPG"]);
+        assert_eq!(report.missing, ["DCIM/IMG_0002.JPG"]);
+        assert_eq!(report.untracked, ["DCIM/IMG_0003.JPG"]);
+    }
+
+    #[test]
+    fn update_index_keeps_files_from_earlier_runs() {
+        let root = tempfile::tempdir().unwrap();
+        let output_path = root.path();
+        let first = extracted(output_path, "IMG_0001.JPG", b"first", Algorithm::Blake3);
+        let removed = extracted(output_path, "IMG_0002.JPG", b"removed", Algorithm::Blake3);
+        update_index(output_path, "F2LXXXXXX01", vec![first, removed], &[]).unwrap();
+        std::fs::remove_file(output_path.join("IMG_0002.JPG")).unwrap();
+
+        let second = extracted(output_path, "IMG_0003.JPG", b"second", Algorithm::Blake3);
+        assert_eq!(
+            update_index(output_path, "F2LXXXXXX01", vec![second], &[]).unwrap(),
+            2
+        );
+        let b3sums = std::fs::read_to_string(output_path.join(B3SUMS_NAME)).unwrap();
+        assert_eq!(b3sums.lines().count(), 2);
+        assert!(!b3sums.contains("IMG_0002.JPG"));
+        assert!(verify_output(output_path, 2).unwrap().is_ok());
+    }
+
+    #[test]
+    fn verify_output_requires_an_index() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(verify_output(root.path(), 1).is_err());
+    }
+
+    #[test]
+    fn update_index_rehashes_files_rewritten_without_hashing() {
+        let root = tempfile::tempdir().unwrap();
+        let output_path = root.path();
+        let first = extracted(output_path, "IMG_0001.JPG", b"first", Algorithm::Blake3);
+        update_index(output_path, "F2LXXXXXX01", vec![first], &[]).unwrap();
+        assert!(output_path.join(B3SUMS_NAME).exists());
+
+        std::fs::write(output_path.join("IMG_0001.JPG"), b"changed").unwrap();
+        let rewritten = ["IMG_0001.JPG".to_string()];
+        update_index(output_path, "F2LXXXXXX01", Vec::new(), &rewritten).unwrap();
+        let index = read_index(output_path).unwrap();
+        assert_eq!(index.files[0].size, 7);
+        assert!(verify_output(output_path, 1).unwrap().is_ok());
+
+        let second = extracted(output_path, "IMG_0001.JPG", b"second", Algorithm::Sha256);
+        update_index(output_path, "F2LXXXXXX01", vec![second], &[]).unwrap();
+        assert!(!output_path.join(B3SUMS_NAME).exists());
+        assert!(verify_output(output_path, 1).unwrap().is_ok());
+    }
+}
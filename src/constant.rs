@@ -1,17 +1,269 @@
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::{crypto, squire, verify};
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
 use std::env;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Enum to represent the different ways to organize the extracted files.
+#[derive(Debug, Clone, Copy)]
+pub enum Organizer {
+    Type,
+    Size,
+    Root,
+    Auto,
+}
+
+/// Enum to represent the ways the progress of an extraction is reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressMode {
+    Bar,
+    Ndjson,
+    None,
+}
+
+/// Enum to represent the state of a backup on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BackupState {
+    Complete,
+    InProgress,
+    Corrupt,
+}
+
+impl std::fmt::Display for BackupState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupState::Complete => write!(f, "Complete"),
+            BackupState::InProgress => write!(f, "In Progress"),
+            BackupState::Corrupt => write!(f, "Corrupt"),
+        }
+    }
+}
+
+/// Enum to represent the format of the manifest that indexes the backup
+///
+/// Backups made by iOS 10 and later use `Manifest.db`, earlier versions use `Manifest.mbdb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ManifestFormat {
+    Database,
+    Mbdb,
+}
+
+impl ManifestFormat {
+    /// Determines the format of the manifest available in a backup directory
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the backup directory
+    ///
+    /// # Returns
+    ///
+    /// An `Option` containing the `ManifestFormat`, or `None` if no manifest is present
+    pub fn detect(path: &Path) -> Option<ManifestFormat> {
+        if path.join("Manifest.db").is_file() {
+            Some(ManifestFormat::Database)
+        } else if path.join("Manifest.mbdb").is_file() {
+            Some(ManifestFormat::Mbdb)
+        } else {
+            None
+        }
+    }
+}
 
 /// Struct to store the backup information
 ///
-/// This includes the path to the backup, serial number, device name, product name, backup date, backup size, and encryption status.
+/// This includes the path to the backup, device identifiers, software versions, backup date, backup size, and encryption status.
+/// Values are stored raw, use the `display_*` methods for human-readable formatting.
+#[derive(Debug, Clone, Serialize)]
 pub struct Backup {
     pub path: PathBuf,
     pub serial_number: String,
+    pub udid: String,
     pub device_name: String,
     pub product_name: String,
-    pub backup_date: String,
-    pub backup_size: String,
-    pub encrypted: String,
+    pub product_type: String,
+    pub ios_version: String,
+    pub build_version: String,
+    pub phone_number: String,
+    pub backup_date: Option<DateTime<Utc>>,
+    pub backup_size: Option<u64>,
+    pub encrypted: bool,
+    pub state: BackupState,
+    pub issues: Vec<String>,
+    pub format: Option<ManifestFormat>,
+    #[serde(skip)]
+    pub keys: Option<crypto::Keys>,
+}
+
+impl Backup {
+    /// Returns the format of the manifest, as detected when the backup was discovered
+    ///
+    /// # Returns
+    ///
+    /// An `Option` containing the `ManifestFormat`, or `None` if no manifest is present
+    pub fn manifest_format(&self) -> Option<ManifestFormat> {
+        self.format
+    }
+
+    /// Resolves the path to the blob of a file in the backup
+    ///
+    /// Legacy backups store blobs flat in the backup directory, while newer backups
+    /// store them in sub-directories named after the first two characters of the file ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_id` - The file ID from the manifest
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - A `PathBuf` with the path to the blob
+    /// * `Err` - If the file ID is not a 40 character hex string, as in a corrupt manifest
+    pub fn blob_path(&self, file_id: &str) -> io::Result<PathBuf> {
+        if !verify::is_hex(file_id, 40) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid file ID '{}' in manifest", file_id),
+            ));
+        }
+        match self.format {
+            Some(ManifestFormat::Mbdb) => Ok(self.path.join(file_id)),
+            _ => Ok(self.path.join(&file_id[..2]).join(file_id)),
+        }
+    }
+
+    /// Unlocks an encrypted backup, so its manifest and files can be decrypted
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The password of the encrypted backup
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - If the backup was unlocked
+    /// * `Err` - If the password is incorrect or the keybag cannot be read
+    pub fn unlock(&mut self, password: &str) -> Result<(), String> {
+        self.keys = Some(crypto::unlock(&self.path, password)?);
+        Ok(())
+    }
+
+    /// Opens a single file from the backup by its domain and relative path
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - The domain of the file, e.g. `HomeDomain`
+    /// * `relative_path` - The path of the file within the domain, e.g. `Library/SMS/sms.db`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - A reader for the content of the file
+    /// * `Err` - If the file is not in the manifest or cannot be read
+    pub fn open(
+        &self,
+        domain: &str,
+        relative_path: &str,
+    ) -> Result<impl Read, Box<dyn std::error::Error>> {
+        let manifest = Manifest::from_backup(self)?;
+        let entry = manifest.find(domain, relative_path)?.ok_or(format!(
+            "'{}/{}' not found in backup",
+            domain, relative_path
+        ))?;
+        if !entry.is_file() {
+            return Err(format!("'{}/{}' is not a file", domain, relative_path).into());
+        }
+        Ok(self.open_entry(&entry)?)
+    }
+
+    /// Opens the blob of a manifest entry, decrypting it if the backup is encrypted
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The manifest entry of the file
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - A `BlobReader` for the content of the file
+    /// * `Err` - If the blob cannot be opened or decrypted
+    pub fn open_entry(&self, entry: &ManifestEntry) -> io::Result<crypto::BlobReader> {
+        let blob_path = self.blob_path(&entry.file_id)?;
+        if !blob_path.exists() && entry.size() == 0 {
+            // Empty files are not always stored as blobs
+            return Ok(crypto::BlobReader::Empty);
+        }
+        let file = File::open(&blob_path)?;
+        if !self.encrypted {
+            return Ok(crypto::BlobReader::Plain(file));
+        }
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let keys = self.keys.as_ref().ok_or(invalid(format!(
+            "Backup '{}' is encrypted, a password is required",
+            self.path.display()
+        )))?;
+        let wrapped = entry
+            .metadata
+            .as_ref()
+            .and_then(|meta| meta.encryption_key.as_ref())
+            .ok_or(invalid(format!(
+                "No encryption key for '{}'",
+                entry.file_id
+            )))?;
+        let key = keys.unwrap_key(wrapped).map_err(invalid)?;
+        Ok(crypto::BlobReader::Encrypted(Box::new(
+            crypto::DecryptReader::new(file, &key, entry.size())?,
+        )))
+    }
+
+    /// Formats the backup date in local time along with the elapsed time
+    ///
+    /// # Returns
+    ///
+    /// A `String` containing the backup date, or `Unknown` if the date is missing
+    pub fn display_date(&self) -> String {
+        match self.backup_date {
+            Some(date) => {
+                let elapsed = (Utc::now() - date).num_seconds().max(0);
+                format!(
+                    "{} ({} ago)",
+                    date.with_timezone(&Local).format("%b %d, %Y %I:%M %p"),
+                    squire::convert_seconds(elapsed, 1)
+                )
+            }
+            None => "Unknown".to_string(),
+        }
+    }
+
+    /// Formats the backup size in human-readable format
+    ///
+    /// # Returns
+    ///
+    /// A `String` containing the backup size, or `-` if the size was not computed
+    pub fn display_size(&self) -> String {
+        self.backup_size
+            .map_or("-".to_string(), squire::size_converter)
+    }
+
+    /// Formats the state of the backup
+    ///
+    /// # Returns
+    ///
+    /// A `String` with either `Complete`, `In Progress` or `Corrupt`
+    pub fn display_state(&self) -> String {
+        self.state.to_string()
+    }
+
+    /// Formats the encryption status
+    ///
+    /// # Returns
+    ///
+    /// A `String` with either `Yes` or `No`
+    pub fn display_encrypted(&self) -> String {
+        if self.encrypted {
+            "Yes".to_string()
+        } else {
+            "No".to_string()
+        }
+    }
 }
 
 /// Struct to store the cargo information gathered at compile time using the `env!` macro.
@@ -57,3 +309,62 @@ pub fn build_info() -> MetaData {
         pkg_version_pre: env!("CARGO_PKG_VERSION_PRE").to_string(),
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Function to describe a backup stored in the given directory
+    pub(crate) fn backup_at(path: &Path, format: Option<ManifestFormat>) -> Backup {
+        Backup {
+            path: path.to_path_buf(),
+            serial_number: "F2LXXXXXX01".to_string(),
+            udid: String::new(),
+            device_name: String::new(),
+            product_name: String::new(),
+            product_type: String::new(),
+            ios_version: String::new(),
+            build_version: String::new(),
+            phone_number: String::new(),
+            backup_date: None,
+            backup_size: None,
+            encrypted: false,
+            state: BackupState::Complete,
+            issues: Vec::new(),
+            format,
+            keys: None,
+        }
+    }
+
+    fn backup(format: Option<ManifestFormat>) -> Backup {
+        backup_at(Path::new("/backups/F2LXXXXXX01"), format)
+    }
+
+    #[test]
+    fn blob_path_follows_format() {
+        let file_id = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(
+            backup(Some(ManifestFormat::Database))
+                .blob_path(file_id)
+                .unwrap(),
+            PathBuf::from("/backups/F2LXXXXXX01/01").join(file_id)
+        );
+        assert_eq!(
+            backup(Some(ManifestFormat::Mbdb))
+                .blob_path(file_id)
+                .unwrap(),
+            PathBuf::from("/backups/F2LXXXXXX01").join(file_id)
+        );
+    }
+
+    #[test]
+    fn blob_path_rejects_malformed_file_id() {
+        let backup = backup(Some(ManifestFormat::Database));
+        for file_id in ["", "a", "../../etc/passwd", &"g".repeat(40)] {
+            assert_eq!(
+                backup.blob_path(file_id).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+    }
+}
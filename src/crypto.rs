@@ -0,0 +1,470 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockDecryptMut, KeyInit, KeyIvInit};
+use aes::Aes256;
+use plist::Value;
+use sha1::Sha1;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Class keys wrapped with the passcode key have this bit set in their `WRAP` attribute
+const WRAP_PASSCODE: u32 = 2;
+
+/// Initial value of the AES key wrap algorithm as defined in RFC 3394
+const WRAP_IV: u64 = 0xA6A6_A6A6_A6A6_A6A6;
+
+/// Number of bytes decrypted at a time while streaming a file
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Struct to store the keys of an unlocked encrypted backup
+///
+/// The class keys are unwrapped from the `BackupKeyBag` in `Manifest.plist` using the backup password.
+#[derive(Clone)]
+pub struct Keys {
+    class_keys: HashMap<u32, Vec<u8>>,
+    manifest_key: Option<Vec<u8>>,
+}
+
+impl std::fmt::Debug for Keys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keys")
+            .field("class_keys", &self.class_keys.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Keys {
+    /// Unwraps a key that is prefixed with its 4 byte little-endian protection class
+    ///
+    /// # Arguments
+    ///
+    /// * `wrapped` - The protection class followed by the wrapped key
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - The unwrapped key
+    /// * `Err` - If the protection class is unknown or the key cannot be unwrapped
+    pub fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, String> {
+        if wrapped.len() < 4 {
+            return Err("Wrapped key is too short".to_string());
+        }
+        let class = u32::from_le_bytes([wrapped[0], wrapped[1], wrapped[2], wrapped[3]]);
+        let class_key = self
+            .class_keys
+            .get(&class)
+            .ok_or(format!("No key available for protection class {}", class))?;
+        aes_unwrap(class_key, &wrapped[4..]).ok_or(format!(
+            "Failed to unwrap key for protection class {}",
+            class
+        ))
+    }
+}
+
+/// Function to split the keybag into its tag, length, value blocks
+///
+/// # Arguments
+///
+/// * `data` - The raw keybag
+///
+/// # Returns
+///
+/// A vector of tuples with the 4 character tag and the value
+fn tlv_blocks(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let tag = &data[offset..offset + 4];
+        let length = u32::from_be_bytes([
+            data[offset + 4],
+            data[offset + 5],
+            data[offset + 6],
+            data[offset + 7],
+        ]) as usize;
+        let end = (offset + 8 + length).min(data.len());
+        blocks.push((tag, &data[offset + 8..end]));
+        offset = end;
+    }
+    blocks
+}
+
+/// Function to convert a 4 byte big-endian value into an integer
+fn be_u32(value: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(value.try_into().ok()?))
+}
+
+/// Function to unwrap a key with the AES key wrap algorithm (RFC 3394)
+///
+/// # Arguments
+///
+/// * `kek` - The key encryption key
+/// * `wrapped` - The wrapped key
+///
+/// # Returns
+///
+/// An `Option` containing the unwrapped key, or `None` if the integrity check fails
+fn aes_unwrap(kek: &[u8], wrapped: &[u8]) -> Option<Vec<u8>> {
+    if wrapped.len() < 24 || wrapped.len() % 8 != 0 {
+        return None;
+    }
+    let cipher = Aes256::new_from_slice(kek).ok()?;
+    let n = wrapped.len() / 8 - 1;
+    let mut a = u64::from_be_bytes(wrapped[..8].try_into().ok()?);
+    let mut r: Vec<[u8; 8]> = wrapped[8..]
+        .chunks(8)
+        .map(|chunk| chunk.try_into().unwrap_or_default())
+        .collect();
+    for j in (0..6).rev() {
+        for i in (1..=n).rev() {
+            let t = (n * j + i) as u64;
+            let mut block = [0u8; 16];
+            block[..8].copy_from_slice(&(a ^ t).to_be_bytes());
+            block[8..].copy_from_slice(&r[i - 1]);
+            cipher.decrypt_block(GenericArray::from_mut_slice(&mut block));
+            a = u64::from_be_bytes(block[..8].try_into().ok()?);
+            r[i - 1].copy_from_slice(&block[8..]);
+        }
+    }
+    if a != WRAP_IV {
+        return None;
+    }
+    Some(r.concat())
+}
+
+/// Function to unlock an encrypted backup with its password
+///
+/// # Arguments
+///
+/// * `backup_path` - The path to the backup directory
+/// * `password` - The password of the encrypted backup
+///
+/// # Returns
+///
+/// * `Ok` - The unwrapped `Keys` of the backup
+/// * `Err` - If the keybag is missing or the password is incorrect
+pub fn unlock(backup_path: &Path, password: &str) -> Result<Keys, String> {
+    let manifest = Value::from_file(backup_path.join("Manifest.plist"))
+        .map_err(|err| format!("Failed to read Manifest.plist: {}", err))?;
+    let manifest = manifest
+        .as_dictionary()
+        .ok_or("Manifest.plist is not a dictionary")?;
+    let keybag = manifest
+        .get("BackupKeyBag")
+        .and_then(Value::as_data)
+        .ok_or("Manifest.plist has no BackupKeyBag")?;
+
+    let mut attributes: HashMap<&[u8], &[u8]> = HashMap::new();
+    let mut classes: Vec<HashMap<&[u8], &[u8]>> = Vec::new();
+    let mut keybag_uuid = false;
+    for (tag, value) in tlv_blocks(keybag) {
+        if tag == b"UUID" {
+            if keybag_uuid {
+                classes.push(HashMap::new());
+            }
+            keybag_uuid = true;
+            continue;
+        }
+        match classes.last_mut() {
+            Some(class) if [&b"CLAS"[..], b"WRAP", b"WPKY", b"KTYP", b"PBKY"].contains(&tag) => {
+                class.insert(tag, value);
+            }
+            _ => {
+                attributes.entry(tag).or_insert(value);
+            }
+        }
+    }
+
+    let attribute = |tag: &[u8]| {
+        attributes.get(tag).copied().ok_or(format!(
+            "Keybag is missing '{}'",
+            String::from_utf8_lossy(tag)
+        ))
+    };
+    let salt = attribute(b"SALT")?;
+    let iterations = be_u32(attribute(b"ITER")?).ok_or("Keybag has an invalid ITER")?;
+    // Backups from iOS 10.2 and later derive the key in two rounds
+    let mut passcode_key = [0u8; 32];
+    match (attributes.get(&b"DPSL"[..]), attributes.get(&b"DPIC"[..])) {
+        (Some(dp_salt), Some(dp_iterations)) => {
+            let dp_iterations = be_u32(dp_iterations).ok_or("Keybag has an invalid DPIC")?;
+            let mut first_round = [0u8; 32];
+            pbkdf2::pbkdf2_hmac::<Sha256>(
+                password.as_bytes(),
+                dp_salt,
+                dp_iterations,
+                &mut first_round,
+            );
+            pbkdf2::pbkdf2_hmac::<Sha1>(&first_round, salt, iterations, &mut passcode_key);
+        }
+        _ => pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), salt, iterations, &mut passcode_key),
+    }
+
+    let mut class_keys = HashMap::new();
+    for class in classes {
+        let (Some(id), Some(wrap), Some(wrapped)) = (
+            class.get(&b"CLAS"[..]).and_then(|v| be_u32(v)),
+            class.get(&b"WRAP"[..]).and_then(|v| be_u32(v)),
+            class.get(&b"WPKY"[..]),
+        ) else {
+            continue;
+        };
+        if wrap & WRAP_PASSCODE == 0 {
+            continue;
+        }
+        let key = aes_unwrap(&passcode_key, wrapped)
+            .ok_or("Incorrect password for the encrypted backup")?;
+        class_keys.insert(id, key);
+    }
+    if class_keys.is_empty() {
+        return Err("No class keys could be unwrapped from the keybag".to_string());
+    }
+    let mut keys = Keys {
+        class_keys,
+        manifest_key: None,
+    };
+    if let Some(wrapped) = manifest.get("ManifestKey").and_then(Value::as_data) {
+        keys.manifest_key = Some(keys.unwrap_key(wrapped)?);
+    }
+    Ok(keys)
+}
+
+/// Function to decrypt the manifest database of an encrypted backup into memory
+///
+/// The plaintext is never written to disk, so it cannot be read by others or left behind.
+///
+/// # Arguments
+///
+/// * `manifest_db_path` - The path to the encrypted manifest database
+/// * `keys` - The unwrapped keys of the backup
+///
+/// # Returns
+///
+/// * `Ok` - The decrypted contents of the database
+/// * `Err` - If the database cannot be decrypted
+pub fn decrypt_manifest_db(manifest_db_path: &Path, keys: &Keys) -> io::Result<Vec<u8>> {
+    let key = keys.manifest_key.as_ref().ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Manifest.plist has no ManifestKey",
+    ))?;
+    let size = fs::metadata(manifest_db_path)?.len();
+    let mut reader = DecryptReader::new(File::open(manifest_db_path)?, key, size)?;
+    let mut decrypted = Vec::with_capacity(size as usize);
+    reader.read_to_end(&mut decrypted)?;
+    // Strip the PKCS#7 padding, which is left in place since the original size is unknown
+    if let Some(&last) = decrypted.last() {
+        if (1..=16).contains(&last) {
+            decrypted.truncate(decrypted.len() - last as usize);
+        }
+    }
+    Ok(decrypted)
+}
+
+/// Reader that decrypts an AES-256-CBC encrypted file while streaming it
+///
+/// Backups use a zero IV, and the output is truncated to the original size to drop the padding.
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    decryptor: cbc::Decryptor<Aes256>,
+    remaining: u64,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Creates a new decrypting reader
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The encrypted source
+    /// * `key` - The unwrapped file key
+    /// * `size` - The size of the decrypted content
+    pub fn new(inner: R, key: &[u8], size: u64) -> io::Result<Self> {
+        let decryptor = cbc::Decryptor::<Aes256>::new_from_slices(key, &[0u8; 16])
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        Ok(DecryptReader {
+            inner,
+            decryptor,
+            remaining: size,
+            buffer: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Reads and decrypts the next chunk, aligned to the AES block size
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let mut filled = 0;
+        while filled < CHUNK_SIZE {
+            match self.inner.read(&mut chunk[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        if filled % 16 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Encrypted file is not aligned to the AES block size",
+            ));
+        }
+        chunk.truncate(filled);
+        for block in chunk.chunks_mut(16) {
+            self.decryptor
+                .decrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        self.buffer = chunk;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        if self.position >= self.buffer.len() {
+            self.fill()?;
+            if self.buffer.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Encrypted file is shorter than expected",
+                ));
+            }
+        }
+        let available = (self.buffer.len() - self.position).min(self.remaining as usize);
+        let length = available.min(buf.len());
+        buf[..length].copy_from_slice(&self.buffer[self.position..self.position + length]);
+        self.position += length;
+        self.remaining -= length as u64;
+        Ok(length)
+    }
+}
+
+/// Reader for the content of a file in the backup, decrypting it if the backup is encrypted
+pub enum BlobReader {
+    Plain(File),
+    Encrypted(Box<DecryptReader<File>>),
+    Empty,
+}
+
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BlobReader::Plain(file) => file.read(buf),
+            BlobReader::Encrypted(reader) => reader.read(buf),
+            BlobReader::Empty => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncrypt;
+
+    /// Function to decode a hex string
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Function to encrypt blocks with AES-256-CBC and a zero IV, as done by the backups
+    fn encrypt(key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let cipher = Aes256::new_from_slice(key).unwrap();
+        let mut previous = [0u8; 16];
+        let mut encrypted = Vec::new();
+        for chunk in plaintext.chunks(16) {
+            let mut block = [0u8; 16];
+            for (i, byte) in block.iter_mut().enumerate() {
+                *byte = chunk[i] ^ previous[i];
+            }
+            cipher.encrypt_block(GenericArray::from_mut_slice(&mut block));
+            encrypted.extend_from_slice(&block);
+            previous = block;
+        }
+        encrypted
+    }
+
+    /// Function to pad a plaintext with PKCS#7
+    fn pad(plaintext: &[u8]) -> Vec<u8> {
+        let padding = 16 - plaintext.len() % 16;
+        let mut padded = plaintext.to_vec();
+        padded.extend(std::iter::repeat(padding as u8).take(padding));
+        padded
+    }
+
+    #[test]
+    fn unwraps_rfc3394_vectors() {
+        let kek = hex("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F");
+        // Section 4.3, 128 bits of key data with a 256-bit KEK
+        assert_eq!(
+            aes_unwrap(
+                &kek,
+                &hex("64E8C3F9CE0F5BA263E9777905818A2A93C8191E7D6E8AE7")
+            ),
+            Some(hex("00112233445566778899AABBCCDDEEFF"))
+        );
+        // Section 4.6, 256 bits of key data with a 256-bit KEK
+        assert_eq!(
+            aes_unwrap(
+                &kek,
+                &hex("28C9F404C4B810F4CBCCB35CFB87F8263F5786E2D80ED326CBC7F0E71A99F43BFB988B9B7A02DD21")
+            ),
+            Some(hex("00112233445566778899AABBCCDDEEFF000102030405060708090A0B0C0D0E0F"))
+        );
+    }
+
+    #[test]
+    fn unwrap_rejects_tampered_or_short_input() {
+        let kek = hex("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F");
+        let mut wrapped = hex("64E8C3F9CE0F5BA263E9777905818A2A93C8191E7D6E8AE7");
+        wrapped[10] ^= 1;
+        assert_eq!(aes_unwrap(&kek, &wrapped), None);
+        assert_eq!(aes_unwrap(&kek, &wrapped[..16]), None);
+        assert_eq!(aes_unwrap(&kek[..16], &wrapped), None);
+    }
+
+    #[test]
+    fn decrypt_reader_truncates_to_size() {
+        let key = [7u8; 32];
+        // Spans several chunks, so the CBC chaining is carried across them
+        let plaintext: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let encrypted = encrypt(&key, &pad(&plaintext));
+        let mut reader =
+            DecryptReader::new(io::Cursor::new(encrypted), &key, plaintext.len() as u64).unwrap();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn decrypt_reader_rejects_short_or_unaligned_input() {
+        let key = [7u8; 32];
+        let encrypted = encrypt(&key, &[0u8; 32]);
+        let mut reader = DecryptReader::new(io::Cursor::new(encrypted.clone()), &key, 64).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let mut reader = DecryptReader::new(io::Cursor::new(&encrypted[..20]), &key, 20).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(DecryptReader::new(io::Cursor::new(encrypted), &key[..5], 32).is_err());
+    }
+
+    #[test]
+    fn decrypts_manifest_into_memory() {
+        let key = [9u8; 32];
+        let plaintext = b"SQLite format 3\0 and the rest of the database".to_vec();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Manifest.db");
+        fs::write(&path, encrypt(&key, &pad(&plaintext))).unwrap();
+        let keys = Keys {
+            class_keys: HashMap::new(),
+            manifest_key: Some(key.to_vec()),
+        };
+        assert_eq!(decrypt_manifest_db(&path, &keys).unwrap(), plaintext);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
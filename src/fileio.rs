@@ -1,13 +1,20 @@
-use crate::parser;
-use crate::{constant, squire};
+use crate::cancel::CancellationToken;
+use crate::checksum;
+use crate::journal;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::observer::ExtractionObserver;
+use crate::throttle::Throttle;
+use crate::{constant, progress, squire, transfer};
 use plist::Value;
-use rusqlite::{Connection, Result};
+use rusqlite::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs::{create_dir_all, File};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
-use threadpool::ThreadPool;
-use tqdm;
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 
 /// Function to retrieve the value of a key from a plist file
 ///
@@ -36,144 +43,1914 @@ pub fn get_plist_key(info: &Option<Value>, key: &str, default: &str) -> String {
     }
 }
 
-/// Function to parse the manifest database
+/// Function to retrieve the boolean value of a key from a plist file
+///
+/// # Arguments
+///
+/// * `info` - The plist file as a `Value`
+/// * `key` - The key to retrieve the value for
+///
+/// # Returns
+///
+/// An `Option` containing the boolean value of the key
+pub fn get_plist_bool(info: &Option<Value>, key: &str) -> Option<bool> {
+    info.as_ref()
+        .and_then(|v| v.as_dictionary()?.get(key))
+        .and_then(Value::as_boolean)
+}
+
+/// Struct to store a single file planned for extraction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedFile {
+    pub serial_number: String,
+    pub file_id: String,
+    pub domain: String,
+    pub relative_path: String,
+    pub size: u64,
+    #[serde(default)]
+    pub modified: Option<i64>,
+    pub destination: PathBuf,
+}
+
+/// Struct to configure an extraction, independent of the command line
+///
+/// Created with `ExtractOptions::default()` and adjusted with the builder methods,
+/// the CLI maps its arguments into it with `ArgConfig::extract_options`.
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    pub output_dir: PathBuf,
+    pub organize: constant::Organizer,
+    pub workers: usize,
+    pub link: bool,
+    pub resume: bool,
+    pub sync: bool,
+    pub delete: bool,
+    pub dry_run: bool,
+    pub checksum: Option<checksum::Algorithm>,
+    pub max_bandwidth: Option<u64>,
+    pub max_iops: Option<u64>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            output_dir: PathBuf::from("extracted"),
+            organize: constant::Organizer::Auto,
+            workers: squire::default_workers(),
+            link: false,
+            resume: false,
+            sync: false,
+            delete: false,
+            dry_run: false,
+            checksum: None,
+            max_bandwidth: None,
+            max_iops: None,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Sets the directory the output directory of each device is created in
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = output_dir.into();
+        self
+    }
+
+    /// Sets the way the extracted files are organized
+    pub fn organize(mut self, organize: constant::Organizer) -> Self {
+        self.organize = organize;
+        self
+    }
+
+    /// Sets the number of files extracted at the same time
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Hard links the files to the blobs in the backup instead of copying them
+    pub fn link(mut self, link: bool) -> Self {
+        self.link = link;
+        self
+    }
+
+    /// Skips the files already completed according to the journal of the output directory
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Only extracts the files that are new or changed since the last extraction
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Deletes the files that are no longer in the backup, used together with `sync`
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.delete = delete;
+        self
+    }
+
+    /// Prints the plan instead of extracting it
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Hashes the extracted files with the given algorithm and writes the checksum manifests
+    pub fn checksum(mut self, checksum: Option<checksum::Algorithm>) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Limits the number of bytes copied per second
+    pub fn max_bandwidth(mut self, max_bandwidth: Option<u64>) -> Self {
+        self.max_bandwidth = max_bandwidth;
+        self
+    }
+
+    /// Limits the number of I/O operations per second
+    pub fn max_iops(mut self, max_iops: Option<u64>) -> Self {
+        self.max_iops = max_iops;
+        self
+    }
+}
+
+/// Function to resolve the destination of a file based on the chosen organizer
+///
+/// # Arguments
+///
+/// * `output_path` - The path to the output directory of the device
+/// * `relative_path` - The relative path of the file
+/// * `size` - The size of the file in bytes
+/// * `organize` - The organizer to arrange the files with
+///
+/// # Returns
+///
+/// A `PathBuf` with the destination of the file
+pub fn destination(
+    output_path: &Path,
+    relative_path: &PathBuf,
+    size: u64,
+    organize: constant::Organizer,
+) -> PathBuf {
+    let filename = relative_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    match organize {
+        constant::Organizer::Type => output_path.join(squire::file_type(relative_path, &filename)),
+        constant::Organizer::Size => output_path.join(squire::file_size(size, &filename)),
+        constant::Organizer::Root => output_path.join(filename).to_owned(),
+        constant::Organizer::Auto => output_path.join(relative_path),
+    }
+}
+
+/// Function to get the output directory of a device
 ///
 /// # Arguments
 ///
-/// * `manifest_db_path` - The path to the manifest database
 /// * `backup` - The backup information
-/// * `arguments` - The command line arguments
+/// * `options` - The options of the extraction
 ///
 /// # Returns
 ///
-/// * `Ok` - If the function completes successfully
+/// A `PathBuf` with the output directory for the files of the backup
+pub fn device_output_dir(backup: &constant::Backup, options: &ExtractOptions) -> PathBuf {
+    options
+        .output_dir
+        .join(format!("{} - {}", backup.device_name, backup.serial_number))
+}
+
+/// Function to resolve the destination of a file to be extracted
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `output_path` - The output directory of the device
+/// * `entry` - The manifest entry to extract
+/// * `organize` - The organizer to arrange the files with
+///
+/// # Returns
+///
+/// A `PlannedFile` struct
+pub fn plan_file(
+    backup: &constant::Backup,
+    output_path: &Path,
+    entry: ManifestEntry,
+    organize: constant::Organizer,
+) -> PlannedFile {
+    let size = match &entry.metadata {
+        Some(meta) => meta.size,
+        None => backup
+            .blob_path(&entry.file_id)
+            .map_or(0, |path| squire::get_size(&path)),
+    };
+    let modified = entry
+        .metadata
+        .as_ref()
+        .map(|meta| meta.last_modified)
+        .filter(|modified| *modified > 0);
+    let relative_path = PathBuf::from(&entry.relative_path);
+    PlannedFile {
+        serial_number: backup.serial_number.clone(),
+        destination: destination(output_path, &relative_path, size, organize),
+        file_id: entry.file_id,
+        domain: entry.domain,
+        relative_path: entry.relative_path,
+        size,
+        modified,
+    }
+}
+
+/// Function to resolve the destination of every file to be extracted
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `entries` - The manifest entries to extract
+/// * `options` - The options of the extraction
+///
+/// # Returns
+///
+/// A vector of `PlannedFile` structs
+pub fn plan_extraction(
+    backup: &constant::Backup,
+    entries: Vec<ManifestEntry>,
+    options: &ExtractOptions,
+) -> Vec<PlannedFile> {
+    let output_path = device_output_dir(backup, options);
+    entries
+        .into_iter()
+        .map(|entry| plan_file(backup, &output_path, entry, options.organize))
+        .collect()
+}
+
+/// Function to print a summary of the planned extraction without copying anything
+///
+/// # Arguments
+///
+/// * `plan` - The files planned for extraction
+/// * `sample` - Number of planned writes to print
+pub fn print_plan(plan: &[PlannedFile], sample: usize) {
+    let mut destinations: HashMap<&Path, Vec<&PlannedFile>> = HashMap::new();
+    for file in plan {
+        destinations
+            .entry(file.destination.as_path())
+            .or_default()
+            .push(file);
+    }
+    let conflicts: Vec<(&&Path, &Vec<&PlannedFile>)> = destinations
+        .iter()
+        .filter(|(_, files)| files.len() > 1)
+        .collect();
+    let existing = destinations
+        .keys()
+        .filter(|destination| destination.exists())
+        .count();
+    let total: u64 = plan.iter().map(|file| file.size).sum();
+
+    println!("\nPlanned writes");
+    println!("{:-<50}", "");
+    for file in plan.iter().take(sample) {
+        println!(
+            "{}/{} -> {}",
+            file.domain,
+            file.relative_path,
+            file.destination.display()
+        );
+    }
+    if plan.len() > sample {
+        println!("... and {} more", plan.len() - sample);
+    }
+    if !conflicts.is_empty() {
+        println!("\nConflicts");
+        println!("{:-<50}", "");
+        for (destination, files) in conflicts.iter().take(sample) {
+            println!("{} <- {} files", destination.display(), files.len());
+            for file in files.iter() {
+                println!("    {}/{}", file.domain, file.relative_path);
+            }
+        }
+        if conflicts.len() > sample {
+            println!("... and {} more", conflicts.len() - sample);
+        }
+    }
+    println!();
+    println!("{:<20} {}", "Files", plan.len());
+    println!("{:<20} {}", "Total size", squire::size_converter(total));
+    println!("{:<20} {}", "Conflicts", conflicts.len());
+    println!("{:<20} {}", "Already existing", existing);
+}
+
+/// Function to check that the output filesystem has enough free space
+///
+/// # Arguments
+///
+/// * `output_dir` - The output directory, which may not exist yet
+/// * `required` - Number of bytes to be written
+///
+/// # Returns
+///
+/// * `Ok` - If there is enough free space, or it cannot be determined
+/// * `Err` - If the free space is insufficient
+pub fn check_free_space(output_dir: &Path, required: u64) -> Result<(), String> {
+    let available = match existing_ancestor(output_dir).map(fs2::available_space) {
+        Some(Ok(available)) => available,
+        Some(Err(err)) => {
+            log::warn!("Failed to determine free space: {}", err);
+            return Ok(());
+        }
+        None => return Ok(()),
+    };
+    if available < required {
+        return Err(format!(
+            "Not enough free space in '{}': {} required, {} available",
+            output_dir.display(),
+            squire::size_converter(required),
+            squire::size_converter(available)
+        ));
+    }
+    log::debug!(
+        "Free space check passed: {} required, {} available",
+        squire::size_converter(required),
+        squire::size_converter(available)
+    );
+    Ok(())
+}
+
+/// Function to check the free space for the extraction of one or more backups into the output directory
+///
+/// Hard links take no space when the output is on the same filesystem as the backup.
+/// Neither do reflinks, but whether the filesystem supports them is only known once a file is copied,
+/// so a shortfall is only a warning when all the files may be cloned, a full disk still aborts the extraction.
+///
+/// # Arguments
+///
+/// * `demands` - The backups with the number of bytes to be extracted from each
+/// * `options` - The options of the extraction
+///
+/// # Returns
+///
+/// * `Ok` - If there is enough free space, or the files may be cloned
+/// * `Err` - If the free space is insufficient
+fn check_output_space(
+    demands: &[(&constant::Backup, u64)],
+    options: &ExtractOptions,
+) -> Result<(), String> {
+    let mut required = 0;
+    let mut cloned = true;
+    for (backup, bytes) in demands {
+        let shared = same_filesystem(&backup.path, &options.output_dir);
+        if shared && options.link {
+            continue;
+        }
+        required += bytes;
+        cloned &= shared && cfg!(target_os = "linux");
+    }
+    match check_free_space(&options.output_dir, required) {
+        Err(err) if cloned => {
+            log::warn!("{}, extracting anyway as the files may be cloned", err);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Function to find the nearest existing ancestor of a path, which may not exist yet
+///
+/// # Arguments
+///
+/// * `path` - The path to look up
+///
+/// # Returns
+///
+/// * `Option<&Path>` - The path itself or its nearest existing ancestor
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                ancestor
+            }
+        })
+        .find(|ancestor| ancestor.exists())
+}
+
+/// Function to check if the output directory is on the same filesystem as the backup
+///
+/// # Arguments
+///
+/// * `backup_path` - The path of the backup
+/// * `output_dir` - The output directory, which may not exist yet
+///
+/// # Returns
+///
+/// * `bool` - If both are on the same filesystem
+#[cfg(unix)]
+fn same_filesystem(backup_path: &Path, output_dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (
+        std::fs::metadata(backup_path),
+        existing_ancestor(output_dir).map(std::fs::metadata),
+    ) {
+        (Ok(backup), Some(Ok(output))) => backup.dev() == output.dev(),
+        _ => false,
+    }
+}
+
+/// Function to check if the output directory is on the same filesystem as the backup
+///
+/// # Returns
+///
+/// * `bool` - Always false, as the filesystem cannot be compared on this platform
+#[cfg(not(unix))]
+fn same_filesystem(_backup_path: &Path, _output_dir: &Path) -> bool {
+    false
+}
+
+/// Function to resolve the media files in the manifest database into an extraction plan
+///
+/// The whole plan is held in memory, to export it. Extractions stream the manifest instead.
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `options` - The options of the extraction
+///
+/// # Returns
+///
+/// * `Ok` - A vector of `PlannedFile` structs
+/// * `Err` - If the function encounters an error
+pub fn manifest_plan(
+    backup: &constant::Backup,
+    options: &ExtractOptions,
+) -> Result<Vec<PlannedFile>, Box<dyn std::error::Error>> {
+    let manifest = Manifest::open(&backup.path)?;
+    let output_path = device_output_dir(backup, options);
+    let plan = manifest
+        .entries()
+        .media()
+        .map(|entry| entry.map(|entry| plan_file(backup, &output_path, entry, options.organize)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(plan)
+}
+
+/// Function to extract the media files in the manifest database, reporting the progress as chosen
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `options` - The options of the extraction
+/// * `progress` - The way the progress is reported
+/// * `cancel` - The token to cancel the extraction with
+///
+/// # Returns
+///
+/// * `Ok` - The `ExtractionReport` of the files extracted
 /// * `Err` - If the function encounters an error
 pub fn parse_manifest_db(
-    manifest_db_path: &Path,
     backup: &constant::Backup,
-    arguments: &parser::ArgConfig,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let conn = Connection::open(manifest_db_path)?;
-
-    // Get count to update progress bar
-    let mut count_stmt = conn.prepare(&format!(
-        "SELECT COUNT(*) FROM Files {}",
-        squire::media_filter()
-    ))?;
-    let count: usize = count_stmt.query_row([], |row| row.get(0))?;
-    let progress_bar_base = Arc::new(Mutex::new(
-        tqdm::tqdm(0..count)
-            .desc(Some("Extracting"))
-            .style(tqdm::Style::Block),
-    ));
-
-    let mut stmt = conn.prepare(&format!(
-        "SELECT fileID, relativePath FROM Files {}",
-        squire::media_filter()
-    ))?;
-    let rows = stmt.query_map([], |row| {
-        let file_id: String = row.get(0)?;
-        let relative_path: String = row.get(1)?;
-        Ok((file_id, relative_path))
-    })?;
-
-    // Create a thread pool with a fixed number of threads
-    let pool = ThreadPool::new(arguments.workers);
-    let (sender, receiver) = channel();
-
-    for file in rows {
-        match file {
-            Ok((file_id, relative_path)) => {
-                let backup_cloned = backup.path.clone();
-                let output_dir_cloned = arguments
-                    .output_dir
-                    .join(format!("{} - {}", backup.device_name, backup.serial_number));
-                let sender_cloned = sender.clone();
-                let organize_cloned = arguments.organize;
-                let progress_bar = Arc::clone(&progress_bar_base);
-                pool.execute(move || {
-                    let result = extract_files(
-                        &backup_cloned,
-                        &output_dir_cloned,
-                        file_id,
-                        &PathBuf::from(relative_path),
-                        organize_cloned,
-                    );
-                    sender_cloned.send(result).expect("Failed to send result");
-                    // Safely update progress bar
-                    let mut progress = progress_bar.lock().unwrap();
-                    progress.pbar.update(1).unwrap();
-                });
+    options: &ExtractOptions,
+    progress: constant::ProgressMode,
+    cancel: &CancellationToken,
+) -> Result<ExtractionReport, Box<dyn std::error::Error>> {
+    let mut progress = progress::Progress::new(progress);
+    let report = extract_manifest_with(backup, options, &mut progress, cancel)?;
+    if !options.dry_run {
+        print_report(&report);
+    }
+    Ok(report)
+}
+
+/// Function to check if the destination of a file is already in sync with the backup
+///
+/// # Arguments
+///
+/// * `file` - The planned file
+/// * `src_path` - The path to the blob in the backup directory
+/// * `link` - Flag to indicate the files are hard linked to the blobs
+///
+/// # Returns
+///
+/// A `bool` indicating if the destination has the same size and modified time as the file in the backup,
+/// or the same content when the backup has no modified time for it,
+/// or is a hard link to the current blob when `link` is set
+fn is_synced(file: &PlannedFile, src_path: &Path, link: bool) -> bool {
+    let metadata = match std::fs::metadata(&file.destination) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    if metadata.len() != file.size {
+        return false;
+    }
+    #[cfg(unix)]
+    if link {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(source) = std::fs::metadata(src_path) {
+            if source.dev() == metadata.dev() && source.ino() == metadata.ino() {
+                return true;
             }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (src_path, link);
+    match file.modified {
+        Some(modified) => metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .is_some_and(|duration| duration.as_secs() as i64 == modified),
+        // Files of the same size cannot be told apart without a modified time, so their content is compared
+        None => same_content(src_path, &file.destination).unwrap_or(false),
+    }
+}
+
+/// Function to compare the content of two files of the same size
+///
+/// # Arguments
+///
+/// * `first` - The path to the first file
+/// * `second` - The path to the second file
+///
+/// # Returns
+///
+/// * `Ok` - A `bool` indicating if both files have the same bytes
+/// * `Err` - If either file cannot be read
+fn same_content(first: &Path, second: &Path) -> std::io::Result<bool> {
+    use std::io::{BufRead, Read};
+    let mut first = std::io::BufReader::new(File::open(first)?);
+    let mut second = std::io::BufReader::new(File::open(second)?);
+    let mut other = vec![0; first.capacity()];
+    loop {
+        let chunk = first.fill_buf()?;
+        if chunk.is_empty() {
+            return Ok(second.fill_buf()?.is_empty());
+        }
+        let length = chunk.len();
+        match second.read_exact(&mut other[..length]) {
+            Ok(()) if other[..length] == *chunk => first.consume(length),
+            Ok(()) => return Ok(false),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Function to find the files in the output directory that are no longer in the backup
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+/// * `planned` - The destinations of all the files in the backup
+///
+/// # Returns
+///
+/// A vector of `PathBuf` with the files that are not part of the plan
+fn stale_files(output_path: &Path, planned: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    let mut stale = Vec::new();
+    let mut pending = vec![output_path.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        let entries = match std::fs::read_dir(&directory) {
+            Ok(entries) => entries,
             Err(err) => {
-                log::error!("Failed to submit thread operation: {}", err);
+                log::warn!("Failed to read '{}': {}", directory.display(), err);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(journal::JOURNAL_NAME)
+                || (directory == output_path
+                    && checksum::MANIFEST_NAMES.contains(&&*entry.file_name().to_string_lossy()))
+            {
+                continue;
+            } else if !planned.contains(&path) {
+                stale.push(path);
             }
         }
     }
-    // Wait for all tasks to complete
-    drop(sender); // Close the sending side of the channel
-    pool.join();
-    for result in receiver {
-        if let Err(err) = result {
-            log::error!("Error processing files: {:?}", err);
+    stale.sort();
+    stale
+}
+
+/// Function to delete the stale files and the directories they leave empty
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+/// * `stale` - The files to be deleted
+fn delete_stale(output_path: &Path, stale: &[PathBuf]) {
+    for path in stale {
+        match std::fs::remove_file(path) {
+            Ok(_) => log::debug!("Deleted: {}", path.display()),
+            Err(err) => log::error!("Failed to delete '{}': {}", path.display(), err),
+        }
+        // Directories are only removed if empty, so the first failure ends the walk
+        for parent in path.ancestors().skip(1) {
+            if parent == output_path || std::fs::remove_dir(parent).is_err() {
+                break;
+            }
         }
     }
-    Ok(())
+    log::info!(
+        "Deleted {} files that are no longer in the backup",
+        stale.len()
+    );
+}
+
+/// Number of files queued per worker, ahead of the ones being extracted
+const QUEUE_DEPTH: usize = 4;
+/// Number of attempts to extract a file when it fails with a transient error
+const MAX_ATTEMPTS: u32 = 4;
+/// Delay before the first retry, doubled for every attempt after
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Enum to classify the errors encountered while extracting a file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// Errors that may succeed when retried, such as I/O errors on network mounts
+    Transient,
+    /// Errors that fail the same way every time, such as a missing blob or a denied permission
+    Permanent,
+    /// The output filesystem is full, which stops the extraction
+    DiskFull,
+}
+
+/// Function to classify an error encountered while extracting a file
+///
+/// # Arguments
+///
+/// * `err` - The error encountered
+///
+/// # Returns
+///
+/// The `ErrorClass` of the error
+pub fn classify(err: &std::io::Error) -> ErrorClass {
+    match err.raw_os_error() {
+        #[cfg(unix)]
+        Some(libc::ENOSPC) | Some(libc::EDQUOT) => return ErrorClass::DiskFull,
+        #[cfg(windows)]
+        Some(39) | Some(112) => return ErrorClass::DiskFull, // ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+        #[cfg(unix)]
+        Some(libc::EIO) | Some(libc::ESTALE) => return ErrorClass::Transient,
+        _ => (),
+    }
+    // Errors from the manifest and the decryption are mostly `Other`, and fail the same way when retried
+    match err.kind() {
+        std::io::ErrorKind::TimedOut
+        | std::io::ErrorKind::Interrupted
+        | std::io::ErrorKind::WouldBlock
+        | std::io::ErrorKind::ConnectionReset => ErrorClass::Transient,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+/// Struct to store a file that could not be extracted
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub domain: String,
+    pub relative_path: String,
+    pub error: String,
+    pub class: ErrorClass,
+}
+
+/// Struct to summarize the outcome of an extraction
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ExtractionReport {
+    pub extracted: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub bytes: u64,
+    pub cancelled: bool,
+    pub aborted: Option<String>,
+    pub failures: Vec<Failure>,
+}
+
+impl ExtractionReport {
+    /// Function to check if the extraction stopped before all the files were processed
+    ///
+    /// # Returns
+    ///
+    /// A `bool` indicating if the extraction was cancelled or aborted
+    pub fn stopped(&self) -> bool {
+        self.cancelled || self.aborted.is_some()
+    }
+}
+
+/// Function to print the files that could not be extracted
+///
+/// # Arguments
+///
+/// * `title` - Title printed above the files
+/// * `failures` - The files that could not be extracted
+/// * `limit` - Maximum number of files to print
+pub fn print_failures(title: &str, failures: &[Failure], limit: usize) {
+    if failures.is_empty() {
+        return;
+    }
+    println!("\n{}", title);
+    println!("{:-<50}", "");
+    for failure in failures.iter().take(limit) {
+        println!(
+            "{}/{} [{:?}]: {}",
+            failure.domain, failure.relative_path, failure.class, failure.error
+        );
+    }
+    if failures.len() > limit {
+        println!("... and {} more", failures.len() - limit);
+    }
+}
+
+/// Function to print the extraction report
+///
+/// # Arguments
+///
+/// * `report` - The extraction report
+pub fn print_report(report: &ExtractionReport) {
+    println!();
+    if let Some(reason) = &report.aborted {
+        println!("Extraction aborted, partial report: {}", reason);
+        println!("{:-<50}", "");
+    } else if report.cancelled {
+        println!("Extraction cancelled, partial report");
+        println!("{:-<50}", "");
+    }
+    println!("{:<20} {}", "Extracted", report.extracted);
+    println!("{:<20} {}", "Size", squire::size_converter(report.bytes));
+    println!("{:<20} {}", "Failed", report.failed);
+    if report.stopped() {
+        println!("{:<20} {}", "Skipped", report.skipped);
+    }
+    print_failures("Failures", &report.failures, 20);
+}
+
+/// Function to extract the planned files from the backup, reporting the progress as chosen
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `plan` - The files planned for extraction
+/// * `options` - The options of the extraction
+/// * `progress` - The way the progress is reported
+/// * `cancel` - The token to cancel the extraction with
+///
+/// # Returns
+///
+/// * `Ok` - The `ExtractionReport` of the files extracted
+/// * `Err` - If there is not enough free space or the journal cannot be opened
+pub fn extract_entries(
+    backup: &constant::Backup,
+    plan: Vec<PlannedFile>,
+    options: &ExtractOptions,
+    progress: constant::ProgressMode,
+    cancel: &CancellationToken,
+) -> Result<ExtractionReport, Box<dyn std::error::Error>> {
+    let mut progress = progress::Progress::new(progress);
+    let report = extract_entries_with(backup, plan, options, &mut progress, cancel)?;
+    if !options.dry_run {
+        print_report(&report);
+    }
+    Ok(report)
+}
+
+/// Struct to share a number of workers between extractions running concurrently
+///
+/// Every worker holds a permit while it extracts a file,
+/// so the permits left idle by one backup are picked up by the others.
+pub struct WorkerBudget {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Struct to hold a permit of the `WorkerBudget`, which is returned when dropped
+pub struct BudgetPermit<'a> {
+    budget: &'a WorkerBudget,
+}
+
+impl WorkerBudget {
+    /// Function to create a budget of workers
+    ///
+    /// # Arguments
+    ///
+    /// * `workers` - Number of files that can be extracted at the same time
+    ///
+    /// # Returns
+    ///
+    /// A `WorkerBudget` struct
+    pub fn new(workers: usize) -> WorkerBudget {
+        WorkerBudget {
+            available: Mutex::new(workers.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Function to wait for a permit to extract a file
+    ///
+    /// # Returns
+    ///
+    /// A `BudgetPermit` that is returned to the budget when dropped
+    pub fn acquire(&self) -> BudgetPermit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        BudgetPermit { budget: self }
+    }
+}
+
+impl Drop for BudgetPermit<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += 1;
+        self.budget.released.notify_one();
+    }
+}
+
+/// Function to extract multiple backups concurrently, sharing the workers and the I/O limits between them
+///
+/// # Arguments
+///
+/// * `backups` - The backups to be extracted
+/// * `options` - The options of the extraction
+/// * `progress` - The way the progress is reported
+/// * `cancel` - The token to cancel all the extractions with
+///
+/// # Returns
+///
+/// A vector with the `ExtractionReport` of each backup, or the error it failed with
+pub fn extract_parallel(
+    backups: &[constant::Backup],
+    options: &ExtractOptions,
+    progress: constant::ProgressMode,
+    cancel: &CancellationToken,
+) -> Vec<Result<ExtractionReport, String>> {
+    let budget = WorkerBudget::new(options.workers);
+    let throttle = Throttle::new(options.max_bandwidth, options.max_iops);
+    let board = Arc::new(Mutex::new(progress::Board::default()));
+    thread::scope(|scope| {
+        let handles: Vec<_> = backups
+            .iter()
+            .map(|backup| {
+                scope.spawn(move || {
+                    let manifest = Manifest::open(&backup.path).map_err(|err| err.to_string())?;
+                    prepare(backup, PlanSource::Manifest(manifest), options)
+                        .map_err(|err| err.to_string())
+                })
+            })
+            .collect();
+        let mut prepared: Vec<_> = handles.into_iter().map(joined).collect();
+        let mut started = Vec::new();
+        for (backup, result) in backups.iter().zip(&prepared) {
+            if let Ok(prepared) = result {
+                if !preview_or_clean(prepared, options) {
+                    continue;
+                }
+                started.push((backup, prepared.bytes));
+            }
+        }
+        if options.dry_run {
+            return prepared
+                .into_iter()
+                .map(|result| result.map(|_| ExtractionReport::default()))
+                .collect();
+        }
+        // The backups are extracted to the same volume at the same time, so the space is checked once for all of them
+        if let Err(err) = check_output_space(&started, options) {
+            return prepared
+                .into_iter()
+                .map(|result| result.and(Err(err.clone())))
+                .collect();
+        }
+        let handles: Vec<_> = backups
+            .iter()
+            .zip(prepared.drain(..))
+            .map(|(backup, result)| {
+                let budget = &budget;
+                let throttle = &throttle;
+                let board = Arc::clone(&board);
+                scope.spawn(move || {
+                    let mut progress = progress::Progress::new(progress).with_board(board);
+                    execute(
+                        backup,
+                        result?,
+                        options,
+                        &mut progress,
+                        cancel,
+                        Some(budget),
+                        throttle,
+                    )
+                    .map_err(|err| err.to_string())
+                })
+            })
+            .collect();
+        handles.into_iter().map(joined).collect()
+    })
+}
+
+/// Function to wait for a thread of a parallel extraction
+///
+/// # Arguments
+///
+/// * `handle` - The handle of the scoped thread
+///
+/// # Returns
+///
+/// * `Result` - The result of the thread, or an error if it panicked
+fn joined<T>(handle: thread::ScopedJoinHandle<'_, Result<T, String>>) -> Result<T, String> {
+    handle
+        .join()
+        .unwrap_or_else(|_| Err("Extraction panicked".to_string()))
+}
+
+/// Function to print the extraction reports of multiple backups as a table
+///
+/// # Arguments
+///
+/// * `backups` - The backups that were extracted
+/// * `reports` - The report of each backup, or the error it failed with
+pub fn print_reports(backups: &[constant::Backup], reports: &[Result<ExtractionReport, String>]) {
+    println!();
+    println!(
+        "{:<20} {:>10} {:>12} {:>8} {:>8}  Status",
+        "Serial Number", "Extracted", "Size", "Failed", "Skipped"
+    );
+    println!("{:-<80}", "");
+    for (backup, report) in backups.iter().zip(reports) {
+        match report {
+            Ok(report) => println!(
+                "{:<20} {:>10} {:>12} {:>8} {:>8}  {}",
+                backup.serial_number,
+                report.extracted,
+                squire::size_converter(report.bytes),
+                report.failed,
+                report.skipped,
+                if report.aborted.is_some() {
+                    "Aborted"
+                } else if report.cancelled {
+                    "Cancelled"
+                } else {
+                    "Completed"
+                }
+            ),
+            Err(err) => println!(
+                "{:<20} {:>10} {:>12} {:>8} {:>8}  {}",
+                backup.serial_number, "-", "-", "-", "-", err
+            ),
+        }
+    }
+    for (backup, report) in backups.iter().zip(reports) {
+        if let Ok(report) = report {
+            let title = format!("Failures for {}", backup.serial_number);
+            print_failures(&title, &report.failures, 20);
+        }
+    }
+}
+
+/// Function to extract the planned files from the backup
+///
+/// With `--dry-run`, the plan is printed instead of being extracted.
+/// Every extracted file is recorded in the journal of the device,
+/// and with `--resume` the files that were already completed are skipped.
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `plan` - The files planned for extraction
+/// * `options` - The options of the extraction
+/// * `observer` - The observer to be notified as the files are extracted
+/// * `cancel` - The token to cancel the extraction with, which returns a report marked as cancelled
+///
+/// # Returns
+///
+/// * `Ok` - The `ExtractionReport` of the files extracted
+/// * `Err` - If there is not enough free space or the journal cannot be opened
+pub fn extract_entries_with(
+    backup: &constant::Backup,
+    plan: Vec<PlannedFile>,
+    options: &ExtractOptions,
+    observer: &mut dyn ExtractionObserver,
+    cancel: &CancellationToken,
+) -> Result<ExtractionReport, Box<dyn std::error::Error>> {
+    let throttle = Throttle::new(options.max_bandwidth, options.max_iops);
+    run_extraction(
+        backup,
+        PlanSource::Files(plan),
+        options,
+        observer,
+        cancel,
+        None,
+        &throttle,
+    )
+}
+
+/// Function to extract the media files in the manifest of the backup
+///
+/// The manifest is streamed, so the files are planned one at a time instead of being collected up front.
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `options` - The options of the extraction
+/// * `observer` - The observer to be notified as the files are extracted
+/// * `cancel` - The token to cancel the extraction with, which returns a report marked as cancelled
+///
+/// # Returns
+///
+/// * `Ok` - The `ExtractionReport` of the files extracted
+/// * `Err` - If the manifest cannot be opened, there is not enough free space or the journal cannot be opened
+pub fn extract_manifest_with(
+    backup: &constant::Backup,
+    options: &ExtractOptions,
+    observer: &mut dyn ExtractionObserver,
+    cancel: &CancellationToken,
+) -> Result<ExtractionReport, Box<dyn std::error::Error>> {
+    let manifest = Manifest::open(&backup.path)?;
+    let throttle = Throttle::new(options.max_bandwidth, options.max_iops);
+    run_extraction(
+        backup,
+        PlanSource::Manifest(manifest),
+        options,
+        observer,
+        cancel,
+        None,
+        &throttle,
+    )
+}
+
+/// Enum for the source of the files an extraction goes through
+enum PlanSource {
+    /// Files resolved ahead, e.g. from a plan file or a search
+    Files(Vec<PlannedFile>),
+    /// Media files read from the manifest as they are needed
+    Manifest(Manifest),
+}
+
+impl PlanSource {
+    /// Function to iterate over the planned files, which can be done more than once
+    ///
+    /// # Arguments
+    ///
+    /// * `backup` - The backup information
+    /// * `options` - The options of the extraction
+    ///
+    /// # Returns
+    ///
+    /// An iterator of `PlannedFile` structs, which ends after the first error reading the manifest
+    fn files<'a>(
+        &'a self,
+        backup: &'a constant::Backup,
+        options: &'a ExtractOptions,
+    ) -> Box<dyn Iterator<Item = Result<PlannedFile>> + 'a> {
+        match self {
+            PlanSource::Files(files) => Box::new(files.iter().cloned().map(Ok)),
+            PlanSource::Manifest(manifest) => {
+                let output_path = device_output_dir(backup, options);
+                Box::new(manifest.entries().media().map(move |entry| {
+                    entry.map(|entry| plan_file(backup, &output_path, entry, options.organize))
+                }))
+            }
+        }
+    }
+}
+
+/// Enum for the reasons a planned file is left out of an extraction
+enum Skip {
+    /// Completed by a previous extraction, with `--resume`
+    Completed,
+    /// Unchanged since the last extraction, with `--sync`
+    Unchanged,
+}
+
+/// Function to open the journal of a previous extraction to resume it
+///
+/// # Arguments
+///
+/// * `output_path` - The output directory of the device
+/// * `options` - The options of the extraction
+///
+/// # Returns
+///
+/// * `Ok` - An `Option` containing the `Journal`, or `None` if not resuming or there is no journal
+/// * `Err` - If the journal cannot be opened
+fn resume_journal(
+    output_path: &Path,
+    options: &ExtractOptions,
+) -> Result<Option<journal::Journal>, Box<dyn std::error::Error>> {
+    if !options.resume || !output_path.join(journal::JOURNAL_NAME).exists() {
+        return Ok(None);
+    }
+    Ok(Some(journal::Journal::open(output_path)?))
+}
+
+/// Function to check if a planned file is left out of the extraction
+///
+/// # Arguments
+///
+/// * `file` - The planned file
+/// * `backup` - The backup information
+/// * `options` - The options of the extraction
+/// * `journal` - The journal of the extraction being resumed, if any
+///
+/// # Returns
+///
+/// An `Option` containing the reason to skip the file, or `None` if it is to be extracted
+fn skip_reason(
+    file: &PlannedFile,
+    backup: &constant::Backup,
+    options: &ExtractOptions,
+    journal: Option<&journal::Journal>,
+) -> Option<Skip> {
+    if journal.is_some_and(|journal| journal.is_completed(file)) {
+        return Some(Skip::Completed);
+    }
+    if options.sync
+        && matches!(backup.blob_path(&file.file_id), Ok(src_path) if is_synced(file, &src_path, options.link))
+    {
+        return Some(Skip::Unchanged);
+    }
+    None
+}
+
+/// Struct to summarize the files an extraction goes through, before any of them is copied
+struct Prepared {
+    source: PlanSource,
+    output_path: PathBuf,
+    files: usize,
+    bytes: u64,
+    stale: Vec<PathBuf>,
+    preview: Vec<PlannedFile>,
+}
+
+/// Function to go through the planned files once, to count the ones to be extracted and find the stale files
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `source` - The files planned for extraction
+/// * `options` - The options of the extraction
+///
+/// # Returns
+///
+/// * `Ok` - The `Prepared` summary of the extraction, which holds on to the source to extract it
+/// * `Err` - If the manifest cannot be read to the end, there are no files to sync with `--delete`,
+///   or the journal cannot be opened
+fn prepare(
+    backup: &constant::Backup,
+    source: PlanSource,
+    options: &ExtractOptions,
+) -> Result<Prepared, Box<dyn std::error::Error>> {
+    let output_path = device_output_dir(backup, options);
+    let resumed = resume_journal(&output_path, options)?;
+    if options.resume && resumed.is_none() {
+        log::warn!(
+            "No journal found in '{}', extracting all files",
+            output_path.display()
+        );
+    }
+    let find_stale = options.sync && options.delete;
+    // Only the destinations are kept, and only when they are needed to find the stale files
+    let mut planned = HashSet::new();
+    let (mut total, mut completed, mut unchanged) = (0, 0, 0);
+    let mut prepared = Prepared {
+        source,
+        output_path,
+        files: 0,
+        bytes: 0,
+        stale: Vec::new(),
+        preview: Vec::new(),
+    };
+    for file in prepared.source.files(backup, options) {
+        // Stop before anything is deleted, as the stale files are only known once the whole manifest is read
+        let file = file?;
+        total += 1;
+        // Files skipped below are still part of the backup, so they are never stale
+        if find_stale {
+            planned.insert(file.destination.clone());
+        }
+        match skip_reason(&file, backup, options, resumed.as_ref()) {
+            Some(Skip::Completed) => completed += 1,
+            Some(Skip::Unchanged) => unchanged += 1,
+            None => {
+                prepared.files += 1;
+                prepared.bytes += file.size;
+                if options.dry_run {
+                    prepared.preview.push(file);
+                }
+            }
+        }
+    }
+    if resumed.is_some() {
+        log::info!(
+            "Resuming extraction, skipping {} of {} files already completed",
+            completed,
+            total
+        );
+    }
+    if options.sync {
+        log::info!(
+            "Syncing {} new or changed files, {} unchanged",
+            prepared.files,
+            unchanged
+        );
+    }
+    if find_stale {
+        if total == 0 {
+            return Err(format!(
+                "No files planned for '{}', refusing to delete the whole output with --delete",
+                backup.serial_number
+            )
+            .into());
+        }
+        prepared.stale = stale_files(&prepared.output_path, &planned);
+    }
+    Ok(prepared)
+}
+
+/// Function to print the plan of a dry run, or delete the stale files before the extraction
+///
+/// # Arguments
+///
+/// * `prepared` - The prepared extraction
+/// * `options` - The options of the extraction
+///
+/// # Returns
+///
+/// * `bool` - If the files are to be extracted
+fn preview_or_clean(prepared: &Prepared, options: &ExtractOptions) -> bool {
+    if options.dry_run {
+        print_plan(&prepared.preview, 10);
+        if options.delete {
+            println!("{:<20} {}", "To be deleted", prepared.stale.len());
+            for path in prepared.stale.iter().take(10) {
+                println!("    {}", path.display());
+            }
+        }
+        return false;
+    }
+    if !prepared.stale.is_empty() {
+        delete_stale(&prepared.output_path, &prepared.stale);
+    }
+    true
+}
+
+/// Function to extract the planned files from the backup, optionally sharing the workers with other backups
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `source` - The files planned for extraction
+/// * `options` - The options of the extraction
+/// * `observer` - The observer to be notified as the files are extracted
+/// * `cancel` - The token to cancel the extraction with
+/// * `budget` - The workers shared with other extractions, if any
+/// * `throttle` - The bandwidth and I/O limits shared by the workers
+///
+/// # Returns
+///
+/// * `Ok` - The `ExtractionReport` of the files extracted
+/// * `Err` - If there is not enough free space or the journal cannot be opened
+fn run_extraction(
+    backup: &constant::Backup,
+    source: PlanSource,
+    options: &ExtractOptions,
+    observer: &mut dyn ExtractionObserver,
+    cancel: &CancellationToken,
+    budget: Option<&WorkerBudget>,
+    throttle: &Throttle,
+) -> Result<ExtractionReport, Box<dyn std::error::Error>> {
+    let prepared = prepare(backup, source, options)?;
+    if !preview_or_clean(&prepared, options) {
+        return Ok(ExtractionReport::default());
+    }
+    check_output_space(&[(backup, prepared.bytes)], options)?;
+    execute(
+        backup, prepared, options, observer, cancel, budget, throttle,
+    )
+}
+
+/// Function to copy the files that were prepared for extraction
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `prepared` - The summary of the files to be extracted, which are filtered again as they are queued
+/// * `options` - The options of the extraction
+/// * `observer` - The observer to be notified as the files are extracted
+/// * `cancel` - The token to cancel the extraction with
+/// * `budget` - The workers shared with other extractions, if any
+/// * `throttle` - The bandwidth and I/O limits shared by the workers
+///
+/// # Returns
+///
+/// * `Ok` - The `ExtractionReport` of the files extracted
+/// * `Err` - If the journal cannot be opened, or the manifest cannot be read to the end
+fn execute(
+    backup: &constant::Backup,
+    prepared: Prepared,
+    options: &ExtractOptions,
+    observer: &mut dyn ExtractionObserver,
+    cancel: &CancellationToken,
+    budget: Option<&WorkerBudget>,
+    throttle: &Throttle,
+) -> Result<ExtractionReport, Box<dyn std::error::Error>> {
+    let Prepared {
+        source,
+        output_path,
+        files: total,
+        bytes,
+        ..
+    } = prepared;
+    let output_path = &output_path;
+    let journal = journal::Journal::open(output_path)?;
+    // The producer reads the journal on its own connection, while the results are recorded below
+    let resumed = resume_journal(output_path, options)?;
+
+    observer.on_start(backup, total, bytes);
+    // Cancelled by the caller, or internally to abort when the disk is full
+    let token = &cancel.child();
+    let mut report = ExtractionReport::default();
+    let mut checksums = Vec::new();
+    let mut rewritten = Vec::new();
+
+    // The files are planned one at a time and both queues are bounded,
+    // so only the files in flight are held in memory when extracting from the manifest
+    let capacity = options.workers.max(1) * QUEUE_DEPTH;
+    let (job_sender, job_receiver) = sync_channel::<PlannedFile>(capacity);
+    let (result_sender, result_receiver) = sync_channel(capacity);
+    let job_receiver = Mutex::new(job_receiver);
+    let produced: Result<()> = thread::scope(|scope| {
+        let producer = scope.spawn(move || {
+            for file in source.files(backup, options) {
+                let file = file?;
+                if skip_reason(&file, backup, options, resumed.as_ref()).is_some() {
+                    continue;
+                }
+                // Stop queuing new files once cancelled
+                if token.is_cancelled() || job_sender.send(file).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        for _ in 0..options.workers.max(1) {
+            let result_sender = result_sender.clone();
+            let job_receiver = &job_receiver;
+            scope.spawn(move || loop {
+                let file = match job_receiver.lock().unwrap().recv() {
+                    Ok(file) => file,
+                    Err(_) => break,
+                };
+                let _permit = budget.map(WorkerBudget::acquire);
+                // Files that were queued before the cancellation are not started
+                let result = token.check().and_then(|_| {
+                    extract_files(
+                        &backup.blob_path(&file.file_id)?,
+                        &file.destination,
+                        file.modified,
+                        options.link,
+                        options.checksum,
+                        token,
+                        throttle,
+                    )
+                });
+                if result_sender.send((file, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Close the sending side of the channel, so the loop below ends with the last worker
+        drop(result_sender);
+        // Record the results as they arrive, so an interrupted extraction can be resumed
+        for (file, result) in result_receiver {
+            match result {
+                Ok(digests) => {
+                    observer.on_file_done(&file);
+                    report.extracted += 1;
+                    report.bytes += file.size;
+                    if let Err(err) = journal.record(&file) {
+                        log::warn!(
+                            "Failed to journal '{}': {}",
+                            file.destination.display(),
+                            err
+                        );
+                    }
+                    match (
+                        digests,
+                        checksum::relative_name(output_path, &file.destination),
+                    ) {
+                        (Some(digests), Some(path)) => checksums.push(checksum::IndexEntry {
+                            path,
+                            size: file.size,
+                            digests,
+                            file_id: file.file_id,
+                            domain: file.domain,
+                            relative_path: file.relative_path,
+                        }),
+                        (None, Some(path)) => rewritten.push(path),
+                        _ => (),
+                    }
+                }
+                Err(err)
+                    if err.kind() == std::io::ErrorKind::Interrupted && token.is_cancelled() => {}
+                Err(err) => {
+                    observer.on_file_error(&file, &err);
+                    report.failed += 1;
+                    let class = classify(&err);
+                    log::error!("Error processing files: {:?}", err);
+                    if class == ErrorClass::DiskFull && report.aborted.is_none() {
+                        log::error!("Output disk is full, aborting the extraction");
+                        report.aborted = Some(format!("Disk full: {}", err));
+                        token.cancel();
+                    }
+                    report.failures.push(Failure {
+                        domain: file.domain,
+                        relative_path: file.relative_path,
+                        error: err.to_string(),
+                        class,
+                    });
+                }
+            }
+        }
+        match producer.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    });
+    // The files are checked again while queued, so the count may differ if the output changed meanwhile
+    report.skipped = total.saturating_sub(report.extracted + report.failed);
+    report.cancelled = cancel.is_cancelled();
+    // The files completed before a cancellation are recorded too, so the manifests match the tree,
+    // and so are the files copied again without --checksum into an output that has an index
+    let indexed = output_path.join(checksum::INDEX_NAME).exists();
+    if options.checksum.is_some() || (indexed && !rewritten.is_empty()) {
+        match checksum::update_index(output_path, &backup.serial_number, checksums, &rewritten) {
+            Ok(count) => log::info!(
+                "Recorded the checksums of {} files in '{}'",
+                count,
+                output_path.join(checksum::SHA256SUMS_NAME).display()
+            ),
+            Err(err) => log::error!("Failed to write the checksum manifests: {}", err),
+        }
+    }
+    observer.on_finish(&report);
+    // The files extracted before the error are journaled, but the extraction did not go through the whole manifest
+    produced?;
+    Ok(report)
+}
+
+/// Function to get the temporary path a file is written to before it is renamed
+///
+/// # Arguments
+///
+/// * `dest_path` - The destination of the file
+///
+/// # Returns
+///
+/// A `PathBuf` with a hidden file next to the destination
+fn partial_path(dest_path: &Path) -> PathBuf {
+    let filename = dest_path.file_name().unwrap_or_default().to_string_lossy();
+    dest_path.with_file_name(format!(".{}.ios-part", filename))
 }
 
 /// Function to extract files from the backup
 ///
+/// Transient errors are retried with an exponential backoff, up to `MAX_ATTEMPTS` times.
+///
 /// # Arguments
 ///
-/// * `backup_path` - The path to the backup directory
-/// * `output_path` - The path to the output directory
-/// * `file_id` - The file ID to extract
-/// * `relative_path` - The relative path of the file
+/// * `src_path` - The path to the blob in the backup directory
+/// * `dest_path` - The destination of the file
+/// * `modified` - The modified time of the file in the backup, to be set on the destination
+/// * `link` - Flag to hard link the blob instead of copying it
+/// * `algorithm` - The algorithm the file must be hashed with while copying, if any
+/// * `cancel` - The token checked while copying large files
+/// * `throttle` - The bandwidth and I/O limits shared with the other workers
 ///
 /// # Returns
 ///
-/// * `Ok` - If the function completes successfully
-/// * `Err` - If the function encounters an error
+/// * `Ok` - The hashes of the extracted file, if they were computed while copying
+/// * `Err` - The last error encountered, or `Interrupted` if the extraction was cancelled
 fn extract_files(
-    backup_path: &Path,
-    output_path: &Path,
-    file_id: String,
-    relative_path: &PathBuf,
-    organize: parser::Organizer,
-) -> std::io::Result<()> {
-    let src_path = backup_path.join(&file_id[..2]).join(file_id);
+    src_path: &Path,
+    dest_path: &Path,
+    modified: Option<i64>,
+    link: bool,
+    algorithm: Option<checksum::Algorithm>,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> std::io::Result<Option<checksum::Digests>> {
+    let mut attempt = 1;
+    loop {
+        match extract_once(
+            src_path, dest_path, modified, link, algorithm, cancel, throttle,
+        ) {
+            Ok(digests) => return Ok(digests),
+            Err(err)
+                if attempt < MAX_ATTEMPTS
+                    && classify(&err) == ErrorClass::Transient
+                    && !cancel.is_cancelled() =>
+            {
+                let delay = RETRY_DELAY * 2u32.pow(attempt - 1);
+                log::warn!(
+                    "Attempt {} of {} failed for '{}', retrying in {:?}: {}",
+                    attempt,
+                    MAX_ATTEMPTS,
+                    src_path.display(),
+                    delay,
+                    err
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Function to make a single attempt at extracting a file from the backup
+///
+/// The file is written to a temporary path and renamed once complete,
+/// so a cancelled extraction never leaves a truncated file at the destination.
+///
+/// # Arguments
+///
+/// * `src_path` - The path to the blob in the backup directory
+/// * `dest_path` - The destination of the file
+/// * `modified` - The modified time of the file in the backup, to be set on the destination
+/// * `link` - Flag to hard link the blob instead of copying it
+/// * `algorithm` - The algorithm the file must be hashed with while copying, if any
+/// * `cancel` - The token checked while copying large files
+/// * `throttle` - The bandwidth and I/O limits shared with the other workers
+///
+/// # Returns
+///
+/// * `Ok` - The hashes of the extracted file, if they were computed while copying
+/// * `Err` - If the function encounters an error, or `Interrupted` if the extraction was cancelled
+fn extract_once(
+    src_path: &Path,
+    dest_path: &Path,
+    modified: Option<i64>,
+    link: bool,
+    algorithm: Option<checksum::Algorithm>,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> std::io::Result<Option<checksum::Digests>> {
     if !src_path.exists() {
         let msg = format!("Path {} doesn't exist", src_path.display());
         log::debug!("{}", msg);
         return Err(std::io::Error::new(std::io::ErrorKind::NotFound, msg));
     }
-    let filename = relative_path
-        .file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-    let dest_path = match organize {
-        parser::Organizer::Type => output_path.join(squire::file_type(relative_path, &filename)),
-        parser::Organizer::Size => output_path.join(squire::file_size(&src_path, &filename)),
-        parser::Organizer::Root => output_path.join(filename).to_owned(),
-        parser::Organizer::Auto => output_path.join(relative_path),
-    };
     if let Some(parent) = dest_path.parent() {
         match create_dir_all(parent) {
             Ok(_) => (),
             Err(err) => return Err(err),
         }
     }
-    let mut src_file = File::open(&src_path)?;
-    let mut dest_file = File::create(&dest_path)?;
-    match std::io::copy(&mut src_file, &mut dest_file) {
-        Ok(_) => (),
-        Err(err) => return Err(err),
+    let tmp_path = partial_path(dest_path);
+    let result = write_partial(
+        src_path, &tmp_path, modified, link, algorithm, cancel, throttle,
+    )
+    .and_then(|digests| std::fs::rename(&tmp_path, dest_path).map(|_| digests));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
     }
+    let digests = result?;
     log::debug!(
         "Extracted: {} -> {}",
         src_path.display(),
         dest_path.display()
     );
-    Ok(())
+    Ok(digests)
+}
+
+/// Function to copy a blob to the temporary path of its destination
+///
+/// # Arguments
+///
+/// * `src_path` - The path to the blob in the backup directory
+/// * `tmp_path` - The temporary path to write to
+/// * `modified` - The modified time of the file in the backup, to be set on the copy
+/// * `link` - Flag to hard link the blob instead of copying it
+/// * `algorithm` - The algorithm the file must be hashed with while copying, if any
+/// * `cancel` - The token checked while copying large files
+/// * `throttle` - The bandwidth and I/O limits shared with the other workers
+///
+/// # Returns
+///
+/// * `Ok` - The hashes of the copy, if they were computed while copying
+/// * `Err` - If the function encounters an error
+fn write_partial(
+    src_path: &Path,
+    tmp_path: &Path,
+    modified: Option<i64>,
+    link: bool,
+    algorithm: Option<checksum::Algorithm>,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> std::io::Result<Option<checksum::Digests>> {
+    let (method, digests) =
+        transfer::copy_file(src_path, tmp_path, link, algorithm, cancel, throttle)?;
+    log::debug!("Copied '{}' with {:?}", src_path.display(), method);
+    // A hard link shares the blob, so its modified time is left untouched
+    let modified = modified
+        .and_then(|modified| u64::try_from(modified).ok())
+        .and_then(|modified| UNIX_EPOCH.checked_add(Duration::from_secs(modified)));
+    if let (Some(modified), false) = (modified, method == transfer::Method::Hardlink) {
+        File::options()
+            .write(true)
+            .open(tmp_path)?
+            .set_modified(modified)?;
+    }
+    Ok(digests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::tests::backup_at;
+    use crate::observer::NoopObserver;
+    use std::fs;
+
+    /// Function to create a backup with a manifest database and a blob for each of the files
+    fn fixture(root: &Path, files: &[(&str, &[u8])]) -> constant::Backup {
+        let path = root.join("F2LXXXXXX01");
+        fs::create_dir_all(&path).unwrap();
+        let conn = rusqlite::Connection::open(path.join("Manifest.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Files (fileID TEXT PRIMARY KEY, domain TEXT, relativePath TEXT, flags INTEGER, file BLOB);",
+        )
+        .unwrap();
+        for (relative_path, content) in files {
+            let file_id = crate::mbdb::file_id("CameraRollDomain", relative_path);
+            conn.execute(
+                "INSERT INTO Files VALUES (?, 'CameraRollDomain', ?, 1, NULL)",
+                rusqlite::params![file_id, relative_path],
+            )
+            .unwrap();
+            fs::create_dir_all(path.join(&file_id[..2])).unwrap();
+            fs::write(path.join(&file_id[..2]).join(&file_id), content).unwrap();
+        }
+        backup_at(&path, Some(constant::ManifestFormat::Database))
+    }
+
+    /// Observer that records the totals it is started with and the files done
+    #[derive(Default)]
+    struct Recorder {
+        started: Option<(usize, u64)>,
+        done: usize,
+    }
+
+    impl ExtractionObserver for Recorder {
+        fn on_start(&mut self, _backup: &constant::Backup, files: usize, bytes: u64) {
+            self.started = Some((files, bytes));
+        }
+
+        fn on_file_done(&mut self, _file: &PlannedFile) {
+            self.done += 1;
+        }
+    }
+
+    #[test]
+    fn streams_media_files_from_manifest() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(
+            root.path(),
+            &[
+                ("Media/DCIM/IMG_0001.JPG", b"first"),
+                ("Media/DCIM/IMG_0002.MOV", b"second"),
+                ("Library/notes.txt", b"not media"),
+            ],
+        );
+        let options = ExtractOptions::default()
+            .output_dir(root.path().join("out"))
+            .workers(1);
+        let mut recorder = Recorder::default();
+        let report =
+            extract_manifest_with(&backup, &options, &mut recorder, &CancellationToken::new())
+                .unwrap();
+        assert_eq!(recorder.started, Some((2, 11)));
+        assert_eq!(recorder.done, 2);
+        assert_eq!((report.extracted, report.bytes, report.skipped), (2, 11, 0));
+        let output_path = device_output_dir(&backup, &options);
+        assert!(output_path.join("Media/DCIM/IMG_0002.MOV").is_file());
+        assert!(!output_path.join("Library/notes.txt").exists());
+    }
+
+    #[test]
+    fn resume_with_sync_and_delete_keeps_extracted_files() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(
+            root.path(),
+            &[
+                ("Media/DCIM/IMG_0001.JPG", b"first"),
+                ("Media/DCIM/IMG_0002.JPG", b"second"),
+                ("Media/DCIM/IMG_0003.MOV", b"third"),
+            ],
+        );
+        let options = ExtractOptions::default()
+            .output_dir(root.path().join("out"))
+            .workers(2);
+        let cancel = CancellationToken::new();
+        let plan = manifest_plan(&backup, &options).unwrap();
+        assert_eq!(plan.len(), 3);
+        let report =
+            extract_entries_with(&backup, plan, &options, &mut NoopObserver, &cancel).unwrap();
+        assert_eq!(report.extracted, 3);
+
+        let output_path = device_output_dir(&backup, &options);
+        let removed = output_path.join("Media/DCIM/IMG_0000.JPG");
+        fs::write(&removed, b"removed from the backup").unwrap();
+
+        let options = options.resume(true).sync(true).delete(true);
+        let plan = manifest_plan(&backup, &options).unwrap();
+        let report =
+            extract_entries_with(&backup, plan, &options, &mut NoopObserver, &cancel).unwrap();
+        assert_eq!(report.extracted, 0);
+        assert!(!removed.exists());
+        for name in ["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.MOV"] {
+            assert!(
+                output_path.join("Media/DCIM").join(name).is_file(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn hard_links_on_the_same_filesystem_need_no_space() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(root.path(), &[]);
+        let output_dir = root.path().join("out").join("nested");
+        assert!(check_free_space(&output_dir, u64::MAX).is_err());
+
+        let options = ExtractOptions::default().output_dir(output_dir).link(true);
+        let demands = [(&backup, u64::MAX / 2), (&backup, u64::MAX / 2)];
+        assert!(check_output_space(&demands, &options).is_ok());
+        assert!(same_filesystem(&backup.path, &options.output_dir));
+    }
+
+    #[test]
+    fn classifies_io_errors() {
+        use std::io::{Error, ErrorKind};
+        #[cfg(unix)]
+        for code in [libc::ENOSPC, libc::EDQUOT] {
+            assert_eq!(
+                classify(&Error::from_raw_os_error(code)),
+                ErrorClass::DiskFull
+            );
+        }
+        for kind in [
+            ErrorKind::NotFound,
+            ErrorKind::PermissionDenied,
+            ErrorKind::InvalidData,
+            ErrorKind::Other,
+        ] {
+            assert_eq!(classify(&Error::from(kind)), ErrorClass::Permanent);
+        }
+        assert_eq!(
+            classify(&Error::other("file is not a database")),
+            ErrorClass::Permanent
+        );
+        for kind in [
+            ErrorKind::TimedOut,
+            ErrorKind::Interrupted,
+            ErrorKind::WouldBlock,
+            ErrorKind::ConnectionReset,
+        ] {
+            assert_eq!(classify(&Error::from(kind)), ErrorClass::Transient);
+        }
+        #[cfg(unix)]
+        for code in [libc::EIO, libc::ESTALE] {
+            assert_eq!(
+                classify(&Error::from_raw_os_error(code)),
+                ErrorClass::Transient
+            );
+        }
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        let root = tempfile::tempdir().unwrap();
+        let dest_path = root.path().join("out").join("IMG_0001.JPG");
+        let started = std::time::Instant::now();
+        let err = extract_files(
+            &root.path().join("missing"),
+            &dest_path,
+            None,
+            false,
+            None,
+            &CancellationToken::new(),
+            &Throttle::new(None, None),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(started.elapsed() < RETRY_DELAY);
+        assert!(!dest_path.exists());
+    }
+
+    #[test]
+    fn missing_blobs_are_reported_as_permanent_failures() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(
+            root.path(),
+            &[
+                ("Media/DCIM/IMG_0001.JPG", b"first"),
+                ("Media/DCIM/IMG_0002.JPG", b"second"),
+            ],
+        );
+        let file_id = crate::mbdb::file_id("CameraRollDomain", "Media/DCIM/IMG_0002.JPG");
+        fs::remove_file(backup.blob_path(&file_id).unwrap()).unwrap();
+        let options = ExtractOptions::default().output_dir(root.path().join("out"));
+        let report = extract_manifest_with(
+            &backup,
+            &options,
+            &mut NoopObserver,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        assert_eq!(report.extracted, 1);
+        assert_eq!(report.failed, 1);
+        assert!(!report.stopped());
+        assert_eq!(report.failures[0].relative_path, "Media/DCIM/IMG_0002.JPG");
+        assert_eq!(report.failures[0].class, ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn unreadable_manifest_fails_the_extraction() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(root.path(), &[]);
+        fs::write(backup.path.join("Manifest.db"), vec![0x5a; 4096]).unwrap();
+        let options = ExtractOptions::default().output_dir(root.path().join("out"));
+        let cancel = CancellationToken::new();
+        assert!(manifest_plan(&backup, &options).is_err());
+        assert!(extract_manifest_with(&backup, &options, &mut NoopObserver, &cancel).is_err());
+    }
+
+    #[test]
+    fn delete_keeps_the_output_unless_the_manifest_is_read() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(root.path(), &[]);
+        let options = ExtractOptions::default()
+            .output_dir(root.path().join("out"))
+            .sync(true)
+            .delete(true);
+        let cancel = CancellationToken::new();
+        let extracted = device_output_dir(&backup, &options).join("DCIM/IMG_0001.JPG");
+        fs::create_dir_all(extracted.parent().unwrap()).unwrap();
+        fs::write(&extracted, b"first").unwrap();
+
+        assert!(extract_manifest_with(&backup, &options, &mut NoopObserver, &cancel).is_err());
+        assert!(extracted.is_file());
+
+        fs::write(backup.path.join("Manifest.db"), vec![0x5a; 4096]).unwrap();
+        assert!(extract_manifest_with(&backup, &options, &mut NoopObserver, &cancel).is_err());
+        assert!(extracted.is_file());
+    }
+
+    #[test]
+    fn sync_compares_content_without_a_modified_time() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(root.path(), &[("Media/DCIM/IMG_0001.JPG", b"first")]);
+        let options = ExtractOptions::default().output_dir(root.path().join("out"));
+        let plan = manifest_plan(&backup, &options).unwrap();
+        let file = &plan[0];
+        assert_eq!(file.modified, None);
+        let src_path = backup.blob_path(&file.file_id).unwrap();
+        fs::create_dir_all(file.destination.parent().unwrap()).unwrap();
+
+        fs::write(&file.destination, b"first").unwrap();
+        assert!(is_synced(file, &src_path, false));
+        fs::write(&file.destination, b"fir5t").unwrap();
+        assert!(!is_synced(file, &src_path, false));
+        fs::write(&file.destination, b"first!").unwrap();
+        assert!(!is_synced(file, &src_path, false));
+    }
+
+    #[test]
+    fn negative_modified_time_is_not_set() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = fixture(root.path(), &[("Media/DCIM/IMG_0001.JPG", b"first")]);
+        let options = ExtractOptions::default().output_dir(root.path().join("out"));
+        let mut plan = manifest_plan(&backup, &options).unwrap();
+        plan[0].modified = Some(-1);
+        let report = extract_entries_with(
+            &backup,
+            plan,
+            &options,
+            &mut NoopObserver,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        assert_eq!(report.extracted, 1);
+    }
 }
@@ -0,0 +1,152 @@
+use crate::constant;
+use crate::manifest::{Manifest, ManifestEntry};
+use regex::Regex;
+
+/// Struct to store the filters for searching files across backups
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub name: Option<Regex>,
+    pub path: Option<Regex>,
+    pub domain: Option<Regex>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub after: Option<i64>,
+    pub before: Option<i64>,
+}
+
+impl Query {
+    /// Checks if a manifest entry satisfies all the filters
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The manifest entry to check
+    ///
+    /// # Returns
+    ///
+    /// A `bool` indicating if the entry matches
+    pub fn matches(&self, entry: &ManifestEntry) -> bool {
+        let name = entry.relative_path.rsplit('/').next().unwrap_or_default();
+        let size = entry.size();
+        let modified = entry.metadata.as_ref().map_or(0, |meta| meta.last_modified);
+        self.name.as_ref().map_or(true, |re| re.is_match(name))
+            && self
+                .path
+                .as_ref()
+                .map_or(true, |re| re.is_match(&entry.relative_path))
+            && self
+                .domain
+                .as_ref()
+                .map_or(true, |re| re.is_match(&entry.domain))
+            && self.min_size.map_or(true, |min| size >= min)
+            && self.max_size.map_or(true, |max| size <= max)
+            && self.after.map_or(true, |after| modified >= after)
+            && self.before.map_or(true, |before| modified < before)
+    }
+}
+
+/// Function to convert a glob pattern into an anchored regular expression
+///
+/// `**` matches across directories, `*` and `?` match within a single path component.
+///
+/// # Arguments
+///
+/// * `pattern` - The glob pattern
+///
+/// # Returns
+///
+/// * `Ok` - The compiled `Regex`
+/// * `Err` - If the resulting expression is invalid
+pub fn glob(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut expression = String::from("(?i)^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                expression.push_str(".*");
+            }
+            '*' => expression.push_str("[^/]*"),
+            '?' => expression.push_str("[^/]"),
+            _ => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+    Regex::new(&expression)
+}
+
+/// Function to search the files of a backup
+///
+/// # Arguments
+///
+/// * `backup` - The backup information, unlocked with `Backup::unlock` if it is encrypted
+/// * `query` - The filters to apply
+///
+/// # Returns
+///
+/// * `Ok` - A vector of the matching `ManifestEntry` structs
+/// * `Err` - If the manifest cannot be read
+pub fn search(
+    backup: &constant::Backup,
+    query: &Query,
+) -> Result<Vec<ManifestEntry>, Box<dyn std::error::Error>> {
+    let manifest = Manifest::from_backup(backup)?;
+    let mut matches = Vec::new();
+    for entry in manifest.entries().files_only() {
+        let entry = entry?;
+        if query.matches(&entry) {
+            matches.push(entry);
+        }
+    }
+    Ok(matches)
+}
+
+/// Function to print the matches of a backup
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `matches` - The matching manifest entries
+pub fn print_matches(backup: &constant::Backup, matches: &[ManifestEntry]) {
+    for entry in matches {
+        println!(
+            "{}\t{}\t{}\t{}",
+            backup.serial_number, entry.domain, entry.relative_path, entry.file_id
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_stays_within_path_components() {
+        let pattern = glob("Media/DCIM/*.jpg").unwrap();
+        assert!(pattern.is_match("Media/DCIM/IMG_0001.JPG"));
+        assert!(!pattern.is_match("Media/DCIM/100APPLE/IMG_0001.JPG"));
+        assert!(!pattern.is_match("Backup/Media/DCIM/IMG_0001.JPG"));
+
+        let pattern = glob("IMG_000?.*").unwrap();
+        assert!(pattern.is_match("IMG_0001.HEIC"));
+        assert!(!pattern.is_match("IMG_00010.HEIC"));
+        assert!(!pattern.is_match("IMG_000/.HEIC"));
+    }
+
+    #[test]
+    fn glob_double_star_crosses_directories() {
+        let pattern = glob("Media/**.mov").unwrap();
+        assert!(pattern.is_match("Media/DCIM/100APPLE/IMG_0001.MOV"));
+        assert!(pattern.is_match("Media/IMG_0001.MOV"));
+        assert!(!pattern.is_match("Other/IMG_0001.MOV"));
+        assert!(glob("**").unwrap().is_match(""));
+    }
+
+    #[test]
+    fn glob_escapes_regex_characters() {
+        let pattern = glob("Media/a+b (1)[2].{jpg}$").unwrap();
+        assert!(pattern.is_match("Media/a+b (1)[2].{jpg}$"));
+        assert!(!pattern.is_match("Media/aab (1)2.jpg"));
+        assert!(!glob(".jpg").unwrap().is_match("xjpg"));
+        assert!(glob("").unwrap().is_match(""));
+    }
+}
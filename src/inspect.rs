@@ -0,0 +1,289 @@
+use crate::{constant, fileio, mbdb};
+use chrono::{DateTime, Local, Utc};
+use plist::Value;
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Struct to store the detailed information of a backup
+///
+/// Gathered from `Info.plist`, `Manifest.plist`, `Status.plist` and `Manifest.db` (or the legacy `Manifest.mbdb`)
+#[derive(Debug, Clone, Serialize)]
+pub struct Inspection {
+    pub serial_number: String,
+    pub udid: String,
+    pub device_name: String,
+    pub product_name: String,
+    pub product_type: String,
+    pub ios_version: String,
+    pub build_version: String,
+    pub phone_number: String,
+    pub imei: String,
+    pub meid: String,
+    pub backup_date: Option<DateTime<Utc>>,
+    pub encrypted: bool,
+    pub state: constant::BackupState,
+    pub issues: Vec<String>,
+    pub installed_apps: Option<usize>,
+    pub manifest_apps: Option<usize>,
+    pub manifest_version: String,
+    pub was_passcode_set: Option<bool>,
+    pub snapshot_state: String,
+    pub backup_state: String,
+    pub is_full_backup: Option<bool>,
+    pub status_date: Option<DateTime<Utc>>,
+    pub schema_version: Option<i64>,
+    pub file_count: Option<u64>,
+    pub directory_count: Option<u64>,
+    pub domain_count: Option<u64>,
+}
+
+/// Function to load a plist file
+///
+/// # Arguments
+///
+/// * `path` - The path to the plist file
+///
+/// # Returns
+///
+/// An `Option` containing the plist as a `Value`
+fn load_plist(path: &Path) -> Option<Value> {
+    match Value::from_file(path) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log::debug!("Failed to read '{}': {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Function to retrieve the date value of a key from a plist file
+///
+/// # Arguments
+///
+/// * `info` - The plist file as a `Value`
+/// * `key` - The key to retrieve the value for
+///
+/// # Returns
+///
+/// An `Option` containing the date in UTC
+fn get_plist_date(info: &Option<Value>, key: &str) -> Option<DateTime<Utc>> {
+    info.as_ref()
+        .and_then(|v| v.as_dictionary()?.get(key))
+        .and_then(Value::as_date)
+        .map(|date| DateTime::<Utc>::from(SystemTime::from(date)))
+}
+
+/// Function to count the entries of an array or dictionary in a plist file
+///
+/// # Arguments
+///
+/// * `info` - The plist file as a `Value`
+/// * `key` - The key to count the entries for
+///
+/// # Returns
+///
+/// An `Option` containing the number of entries
+fn get_plist_len(info: &Option<Value>, key: &str) -> Option<usize> {
+    match info.as_ref().and_then(|v| v.as_dictionary()?.get(key))? {
+        Value::Array(array) => Some(array.len()),
+        Value::Dictionary(dict) => Some(dict.len()),
+        _ => None,
+    }
+}
+
+/// Function to run a single value query against the manifest database
+///
+/// # Arguments
+///
+/// * `conn` - The connection to the manifest database
+/// * `query` - The query to execute
+///
+/// # Returns
+///
+/// An `Option` containing the queried value
+fn query_value(conn: &Connection, query: &str) -> Option<i64> {
+    match conn.query_row(query, [], |row| row.get(0)) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log::debug!("Failed to execute '{}': {}", query, err);
+            None
+        }
+    }
+}
+
+/// Function to inspect a backup
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+///
+/// # Returns
+///
+/// An `Inspection` struct with all the details that could be read
+pub fn inspect(backup: &constant::Backup) -> Inspection {
+    let info = load_plist(&backup.path.join("Info.plist"));
+    let manifest = load_plist(&backup.path.join("Manifest.plist"));
+    let status = load_plist(&backup.path.join("Status.plist"));
+
+    let conn = Connection::open_with_flags(
+        backup.path.join("Manifest.db"),
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )
+    .map_err(|err| log::debug!("Failed to open Manifest.db: {}", err))
+    .ok();
+    let db_value = |query: &str| conn.as_ref().and_then(|c| query_value(c, query));
+    let mut file_count = db_value("SELECT COUNT(*) FROM Files WHERE flags = 1").map(|v| v as u64);
+    let mut directory_count =
+        db_value("SELECT COUNT(*) FROM Files WHERE flags = 2").map(|v| v as u64);
+    let mut domain_count = db_value("SELECT COUNT(DISTINCT domain) FROM Files").map(|v| v as u64);
+    if backup.manifest_format() == Some(constant::ManifestFormat::Mbdb) {
+        match mbdb::parse(&backup.path.join("Manifest.mbdb")) {
+            Ok(entries) => {
+                let count = |flags| entries.iter().filter(|e| e.flags == flags).count() as u64;
+                file_count = Some(count(1));
+                directory_count = Some(count(2));
+                domain_count = Some(
+                    entries
+                        .iter()
+                        .map(|e| &e.domain)
+                        .collect::<HashSet<_>>()
+                        .len() as u64,
+                );
+            }
+            Err(err) => log::debug!("Failed to parse Manifest.mbdb: {}", err),
+        }
+    }
+
+    Inspection {
+        serial_number: backup.serial_number.clone(),
+        udid: backup.udid.clone(),
+        device_name: backup.device_name.clone(),
+        product_name: backup.product_name.clone(),
+        product_type: backup.product_type.clone(),
+        ios_version: backup.ios_version.clone(),
+        build_version: backup.build_version.clone(),
+        phone_number: backup.phone_number.clone(),
+        imei: fileio::get_plist_key(&info, "IMEI", ""),
+        meid: fileio::get_plist_key(&info, "MEID", ""),
+        backup_date: backup.backup_date,
+        encrypted: backup.encrypted,
+        state: backup.state,
+        issues: backup.issues.clone(),
+        installed_apps: get_plist_len(&info, "Installed Applications"),
+        manifest_apps: get_plist_len(&manifest, "Applications"),
+        manifest_version: fileio::get_plist_key(&manifest, "Version", ""),
+        was_passcode_set: fileio::get_plist_bool(&manifest, "WasPasscodeSet"),
+        snapshot_state: fileio::get_plist_key(&status, "SnapshotState", ""),
+        backup_state: fileio::get_plist_key(&status, "BackupState", ""),
+        is_full_backup: fileio::get_plist_bool(&status, "IsFullBackup"),
+        status_date: get_plist_date(&status, "Date"),
+        schema_version: db_value("PRAGMA user_version"),
+        file_count,
+        directory_count,
+        domain_count,
+    }
+}
+
+/// Function to format an optional value for display
+///
+/// # Arguments
+///
+/// * `value` - The optional value
+///
+/// # Returns
+///
+/// A `String` with the value or `Unknown` when unavailable
+fn display<T: ToString>(value: Option<T>) -> String {
+    value.map_or("Unknown".to_string(), |v| v.to_string())
+}
+
+/// Function to format a boolean value for display
+///
+/// # Arguments
+///
+/// * `value` - The optional boolean value
+///
+/// # Returns
+///
+/// A `String` with `Yes`, `No` or `Unknown`
+fn display_bool(value: Option<bool>) -> String {
+    display(value.map(|v| if v { "Yes" } else { "No" }))
+}
+
+/// Function to print the inspection report
+///
+/// # Arguments
+///
+/// * `inspection` - The inspection report of a backup
+pub fn print_inspection(inspection: &Inspection) {
+    let format_date = |date: Option<DateTime<Utc>>| {
+        display(date.map(|d| d.with_timezone(&Local).format("%b %d, %Y %I:%M %p")))
+    };
+    let non_empty = |value: &String| {
+        if value.is_empty() {
+            "Unknown".to_string()
+        } else {
+            value.clone()
+        }
+    };
+    let sections = [
+        (
+            "Device",
+            vec![
+                ("Device Name", non_empty(&inspection.device_name)),
+                ("Product Name", non_empty(&inspection.product_name)),
+                ("Product Type", non_empty(&inspection.product_type)),
+                ("Serial Number", non_empty(&inspection.serial_number)),
+                ("UDID", non_empty(&inspection.udid)),
+                ("IMEI", non_empty(&inspection.imei)),
+                ("MEID", non_empty(&inspection.meid)),
+                ("Phone Number", non_empty(&inspection.phone_number)),
+                ("iOS Version", non_empty(&inspection.ios_version)),
+                ("Build Version", non_empty(&inspection.build_version)),
+                ("Installed Apps", display(inspection.installed_apps)),
+            ],
+        ),
+        (
+            "Backup",
+            vec![
+                ("Backup Date", format_date(inspection.backup_date)),
+                ("State", inspection.state.to_string()),
+                ("Status Date", format_date(inspection.status_date)),
+                ("Snapshot State", non_empty(&inspection.snapshot_state)),
+                ("Backup State", non_empty(&inspection.backup_state)),
+                ("Full Backup", display_bool(inspection.is_full_backup)),
+                ("Encrypted", display_bool(Some(inspection.encrypted))),
+                ("Passcode Set", display_bool(inspection.was_passcode_set)),
+                ("Backed Up Apps", display(inspection.manifest_apps)),
+            ],
+        ),
+        (
+            "Manifest",
+            vec![
+                ("Manifest Version", non_empty(&inspection.manifest_version)),
+                ("Schema Version", display(inspection.schema_version)),
+                ("Files", display(inspection.file_count)),
+                ("Directories", display(inspection.directory_count)),
+                ("Domains", display(inspection.domain_count)),
+            ],
+        ),
+    ];
+    for (title, rows) in sections {
+        println!("\n{}", title);
+        println!("{:-<50}", "");
+        for (key, value) in rows {
+            println!("{:<20} {}", key, value);
+        }
+    }
+    if !inspection.issues.is_empty() {
+        println!("\nIssues");
+        println!("{:-<50}", "");
+        for issue in &inspection.issues {
+            println!("{}", issue);
+        }
+    }
+    println!();
+}
@@ -0,0 +1,169 @@
+use crate::fileio::PlannedFile;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Name of the journal file stored in the output directory of each device
+pub const JOURNAL_NAME: &str = ".ios-journal.db";
+
+/// Struct to record the files that were extracted successfully
+///
+/// The journal is a small SQLite database in the output directory of the device,
+/// with a row per completed file that stores the size and modified time of the copy.
+/// Resuming only compares those, the content of extracted files can be checked with `--checksum` and `verify-output`.
+pub struct Journal {
+    connection: Connection,
+    pub path: PathBuf,
+}
+
+/// Function to get the modified time of a file in nanoseconds
+///
+/// # Arguments
+///
+/// * `metadata` - The metadata of the file
+///
+/// # Returns
+///
+/// An `i64` with the modified time, or `0` if it is unavailable
+fn modified_nanos(metadata: &std::fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+impl Journal {
+    /// Function to open the journal in an output directory, creating it if necessary
+    ///
+    /// # Arguments
+    ///
+    /// * `output_path` - The output directory of the device
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - The opened `Journal`
+    /// * `Err` - If the journal cannot be opened
+    pub fn open(output_path: &Path) -> Result<Journal, Box<dyn std::error::Error>> {
+        create_dir_all(output_path)?;
+        let path = output_path.join(JOURNAL_NAME);
+        let connection = Connection::open(&path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS completed (
+                file_id TEXT NOT NULL,
+                destination TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                completed_at INTEGER NOT NULL,
+                PRIMARY KEY (file_id, destination)
+            );",
+        )?;
+        Ok(Journal { connection, path })
+    }
+
+    /// Function to check if a file was already extracted and is still intact
+    ///
+    /// A file is only treated as completed if the destination still has the size and modified time
+    /// recorded in the journal, so restarts do not need to re-read every extracted file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The planned file
+    ///
+    /// # Returns
+    ///
+    /// A `bool` indicating if the file can be skipped
+    pub fn is_completed(&self, file: &PlannedFile) -> bool {
+        let recorded: Option<(i64, i64)> = self
+            .connection
+            .query_row(
+                "SELECT size, modified FROM completed WHERE file_id = ? AND destination = ?",
+                params![file.file_id, file.destination.to_string_lossy()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .unwrap_or_else(|err| {
+                log::warn!("Failed to read journal: {}", err);
+                None
+            });
+        let (size, modified) = match recorded {
+            Some(recorded) => recorded,
+            None => return false,
+        };
+        match std::fs::metadata(&file.destination) {
+            Ok(metadata) => {
+                metadata.len() as i64 == size
+                    && size as u64 == file.size
+                    && modified_nanos(&metadata) == modified
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Function to record a file as extracted
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The planned file
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - If the file was recorded
+    /// * `Err` - If the destination or the journal cannot be accessed
+    pub fn record(&self, file: &PlannedFile) -> Result<(), Box<dyn std::error::Error>> {
+        let metadata = std::fs::metadata(&file.destination)?;
+        self.connection.execute(
+            "INSERT OR REPLACE INTO completed (file_id, destination, size, modified, completed_at)
+            VALUES (?, ?, ?, ?, strftime('%s', 'now'))",
+            params![
+                file.file_id,
+                file.destination.to_string_lossy(),
+                metadata.len() as i64,
+                modified_nanos(&metadata)
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn completed_until_size_or_modified_time_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("IMG_0001.JPG");
+        fs::write(&destination, b"image").unwrap();
+        let file = PlannedFile {
+            serial_number: "F2LXXXXXX01".to_string(),
+            file_id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            domain: "CameraRollDomain".to_string(),
+            relative_path: "Media/DCIM/IMG_0001.JPG".to_string(),
+            size: 5,
+            modified: None,
+            destination: destination.clone(),
+        };
+        let journal = Journal::open(dir.path()).unwrap();
+        assert!(!journal.is_completed(&file));
+        journal.record(&file).unwrap();
+        assert!(journal.is_completed(&file));
+
+        let handle = fs::File::options().write(true).open(&destination).unwrap();
+        handle
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(!journal.is_completed(&file));
+        journal.record(&file).unwrap();
+        fs::write(&destination, b"edited").unwrap();
+        assert!(!journal.is_completed(&file));
+        fs::remove_file(&destination).unwrap();
+        assert!(!journal.is_completed(&file));
+    }
+}
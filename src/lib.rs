@@ -3,20 +3,270 @@
 
 /// Module to handle backup operations
 pub mod backup;
+/// Module to browse a backup like a filesystem
+pub mod browse;
+/// Module to cancel an extraction in progress
+pub mod cancel;
+/// Module to hash the extracted files and verify them against checksum manifests
+pub mod checksum;
 /// Module to load the required structs
 pub mod constant;
+/// Module to decrypt encrypted backups
+pub mod crypto;
 /// Module for database utilities
 pub mod dbutil;
 /// Module to handle database operations
 pub mod fileio;
+/// Module to search files across backups
+pub mod find;
+/// Module to inspect the backup metadata
+pub mod inspect;
+/// Module to journal the extracted files for resumable extraction
+pub mod journal;
 /// Module to construct a custom logger
 pub mod logger;
+/// Module to iterate over the entries of a backup manifest
+pub mod manifest;
+/// Module to parse the legacy manifest of backups from iOS 9 and earlier
+pub mod mbdb;
+/// Module to decode the file metadata stored in the manifest database
+pub mod metadata;
+/// Module to observe the extraction of a backup
+pub mod observer;
 /// Module to parse command line arguments
 pub mod parser;
+/// Module to export and replay extraction plans
+pub mod plan;
+/// Module to report the progress of an extraction
+pub mod progress;
 /// Module for helper functions
 pub mod squire;
+/// Module to aggregate the disk usage of a backup
+pub mod stats;
+/// Module to limit the bandwidth and I/O operations of an extraction
+pub mod throttle;
+/// Module to copy files using kernel offload where available
+pub mod transfer;
+/// Module to verify the integrity of a backup
+pub mod verify;
 
 use rusqlite::Result;
+use std::sync::OnceLock;
+
+/// Cancellation token used by the CLI, cancelled by the signal handler
+static SIGNAL_TOKEN: OnceLock<cancel::CancellationToken> = OnceLock::new();
+
+/// Function to get the cancellation token that is cancelled on SIGINT or SIGTERM
+///
+/// # Returns
+///
+/// A reference to the process wide `CancellationToken`
+fn signal_token() -> &'static cancel::CancellationToken {
+    SIGNAL_TOKEN.get_or_init(cancel::CancellationToken::new)
+}
+
+/// Function to install a handler for SIGINT and SIGTERM to stop the extraction gracefully
+///
+/// The first signal cancels the `signal_token`, so no new files are extracted and the copies in flight are cleaned up.
+/// A second signal exits immediately.
+fn install_signal_handler() {
+    let token = signal_token().clone();
+    let result = ctrlc::set_handler(move || {
+        if token.cancel() {
+            std::process::exit(130);
+        }
+        eprintln!("\nInterrupted, cancelling the extraction (press Ctrl+C again to exit)");
+    });
+    if let Err(err) = result {
+        log::warn!("Failed to install signal handler: {}", err);
+    }
+}
+
+/// Function to find a backup and unlock it with the password if it is encrypted
+///
+/// # Arguments
+///
+/// * `backups` - A slice of `Backup` structs
+/// * `target` - Serial number or UDID of the backup
+/// * `password` - Password for the backup, only used if the backup is encrypted
+///
+/// # Returns
+///
+/// * `Ok` - The `Backup` ready to be read
+/// * `Err` - If the backup is not found or cannot be unlocked
+fn unlocked_backup(
+    backups: &[constant::Backup],
+    target: &str,
+    password: &str,
+) -> Result<constant::Backup, String> {
+    let mut backup = backup::find_backup(backups, target)
+        .ok_or(format!("No backup found for '{}'", target))?
+        .clone();
+    if backup.encrypted {
+        if password.is_empty() {
+            return Err(format!(
+                "Backup '{}' is encrypted, please provide the password (--password)",
+                backup.path.display()
+            ));
+        }
+        backup.unlock(password)?;
+    }
+    Ok(backup)
+}
+
+/// Function to check if a backup can be extracted based on its state and encryption
+///
+/// # Arguments
+///
+/// * `backup` - The backup information
+/// * `force` - Flag to extract backups that are flagged as in progress or corrupt
+///
+/// # Returns
+///
+/// A `bool` indicating if the backup should be extracted
+fn extractable(backup: &constant::Backup, force: bool) -> bool {
+    // Encrypted backups pass validation without reading the manifest, which the extraction cannot decrypt
+    if backup.encrypted {
+        log::error!(
+            "Backup '{}' is encrypted and cannot be extracted, skipping",
+            backup.path.display()
+        );
+        return false;
+    }
+    if backup.state != constant::BackupState::Complete {
+        log::warn!(
+            "Backup '{}' for '{}' is flagged as {}: {}",
+            backup.path.display(),
+            backup.serial_number,
+            backup.display_state(),
+            backup.issues.join(", ")
+        );
+        if !force {
+            log::warn!(
+                "Skipping backup '{}', use --force to extract anyway",
+                backup.path.display()
+            );
+            return false;
+        }
+    }
+    true
+}
+
+/// Function to write an extraction plan instead of extracting the files
+///
+/// # Arguments
+///
+/// * `path` - The path to the plan file
+/// * `plan` - The files planned for extraction
+///
+/// # Returns
+///
+/// * `Ok` - If the plan was written successfully
+/// * `Err` - If the function encounters an error
+fn export_plan(path: &std::path::Path, plan: &[fileio::PlannedFile]) -> Result<String, String> {
+    plan::write_plan(path, plan).map_err(|err| err.to_string())?;
+    log::info!(
+        "Exported {} planned files to '{}'",
+        plan.len(),
+        path.display()
+    );
+    Ok("".into())
+}
+
+/// Function to extract the files listed in a plan file
+///
+/// # Arguments
+///
+/// * `backups` - A slice of `Backup` structs
+/// * `path` - The path to the plan file
+/// * `arguments` - The command line arguments
+///
+/// # Returns
+///
+/// * `Ok` - If the function completes successfully
+/// * `Err` - If the plan is invalid or refers to a backup that cannot be extracted
+fn replay_plan(
+    backups: &[constant::Backup],
+    path: &std::path::Path,
+    arguments: &parser::ArgConfig,
+) -> Result<String, String> {
+    let plan = plan::read_plan(path).map_err(|err| err.to_string())?;
+    let options = arguments.extract_options();
+    let mut grouped: std::collections::BTreeMap<String, Vec<fileio::PlannedFile>> =
+        std::collections::BTreeMap::new();
+    for file in plan {
+        grouped
+            .entry(file.serial_number.clone())
+            .or_default()
+            .push(file);
+    }
+    log::info!(
+        "Loaded {} backups from plan '{}'",
+        grouped.len(),
+        path.display()
+    );
+    for (serial_number, files) in grouped {
+        let backup = backup::find_backup(backups, &serial_number)
+            .ok_or(format!("No backup found for '{}'", serial_number))?;
+        if backup.encrypted {
+            return Err(format!(
+                "Backup '{}' is encrypted and cannot be extracted",
+                backup.path.display()
+            ));
+        }
+        if !extractable(backup, arguments.force) {
+            continue;
+        }
+        plan::check_destinations(&files, &fileio::device_output_dir(backup, &options))?;
+        log::info!(
+            "Extracting {} planned files for '{}'",
+            files.len(),
+            serial_number
+        );
+        let report =
+            fileio::extract_entries(backup, files, &options, arguments.progress, signal_token())
+                .map_err(|err| err.to_string())?;
+        if report.stopped() {
+            return Err("Extraction stopped before completion".into());
+        }
+    }
+    Ok("".into())
+}
+
+/// Function to verify extracted files against the checksum manifests written with `--checksum`
+///
+/// # Arguments
+///
+/// * `path` - An output directory of a device, or the directory that holds them
+/// * `workers` - Number of files to hash at the same time
+///
+/// # Returns
+///
+/// * `Ok` - If every file matches its checksum
+/// * `Err` - If no manifests are found, or any file was altered
+fn verify_output(path: &std::path::Path, workers: usize) -> Result<String, String> {
+    let directories = checksum::output_dirs(path);
+    if directories.is_empty() {
+        return Err(format!(
+            "No '{}' found in '{}', extract with '--checksum' first",
+            checksum::INDEX_NAME,
+            path.display()
+        ));
+    }
+    let mut altered = Vec::new();
+    for directory in directories {
+        log::info!("Verifying output: '{}'", directory.display());
+        let report = checksum::verify_output(&directory, workers).map_err(|err| err.to_string())?;
+        checksum::print_report(&report);
+        if !report.is_ok() {
+            altered.push(directory.display().to_string());
+        }
+    }
+    if !altered.is_empty() {
+        return Err(format!("Output failed verification: {:?}", altered));
+    }
+    Ok("".into())
+}
 
 /// Function to parse and extract iOS backup data
 ///
@@ -29,64 +279,252 @@ pub fn extractor() -> Result<String, String> {
     let arguments = parser::arguments(&metadata);
     let empty_serial = arguments.serial_numbers.is_empty();
     let no_filter = arguments.list || arguments.all;
-    if empty_serial && !no_filter {
+    let extract = matches!(arguments.command, parser::Command::Extract);
+    let replay = arguments.plan.is_some();
+    if extract && empty_serial && !no_filter && !replay {
         return Err(
             "Please provide a serial number (--serial) or use all (--all) / list (--list) options."
                 .into(),
         );
     }
     log::set_logger(&logger::SimpleLogger).unwrap();
-    if arguments.debug {
+    if matches!(arguments.command, parser::Command::Cat(..)) {
+        // Keep stdout clean for the content of the file
+        log::set_max_level(log::LevelFilter::Off);
+    } else if arguments.debug {
         log::set_max_level(log::LevelFilter::Debug);
         log::debug!("Debug mode enabled!!")
     } else {
         log::set_max_level(log::LevelFilter::Info);
     }
+    if extract || arguments.extract {
+        install_signal_handler();
+    }
+    if let parser::Command::VerifyOutput(path) = &arguments.command {
+        // The extracted files are verified on their own, so the backups are not needed
+        let path = path.as_ref().unwrap_or(&arguments.output_dir);
+        return verify_output(path, arguments.workers);
+    }
+    let options = arguments.extract_options();
     log::info!(
         "Searching for backup data in '{}'",
         &arguments.backup_dir.display()
     );
-    let backups = backup::get_backups(&arguments.backup_dir, &arguments.serial_numbers, no_filter);
+    // Only the targeted backups are loaded, as each one is validated by opening its manifest
+    let targets = match &arguments.command {
+        parser::Command::Inspect(target)
+        | parser::Command::Cat(target, _)
+        | parser::Command::Ls(target, _)
+        | parser::Command::Stats(target)
+        | parser::Command::Verify(target) => vec![target.clone()],
+        _ => arguments.serial_numbers.clone(),
+    };
+    let mut backups = backup::get_backups(
+        &arguments.backup_dir,
+        &targets,
+        no_filter || replay || targets.is_empty(),
+    );
     if backups.is_empty() {
-        let err = if empty_serial {
+        let err = if targets.is_empty() {
             format!("No backups found in '{}'", arguments.backup_dir.display())
         } else {
             format!(
-                "No backups found for serial(s) '{:?}' in '{}'",
-                arguments.serial_numbers,
+                "No backups found for '{:?}' in '{}'",
+                targets,
                 arguments.backup_dir.display()
             )
         };
         return Err(err);
     }
     if arguments.list {
+        if !arguments.no_size {
+            backup::compute_sizes(&mut backups, arguments.workers);
+        }
         backup::list_backups(&backups);
         return Ok("".into());
     }
+    match &arguments.command {
+        parser::Command::Inspect(target) => {
+            let backup = backup::find_backup(&backups, target)
+                .ok_or(format!("No backup found for '{}'", target))?;
+            inspect::print_inspection(&inspect::inspect(backup));
+            return Ok("".into());
+        }
+        parser::Command::Cat(target, path) => {
+            let backup = unlocked_backup(&backups, target, &arguments.password)?;
+            let (domain, relative_path) = path.split_once('/').ok_or(format!(
+                "'{}' must be in the form Domain/relativePath",
+                path
+            ))?;
+            let mut reader = backup
+                .open(domain, relative_path)
+                .map_err(|err| err.to_string())?;
+            std::io::copy(&mut reader, &mut std::io::stdout().lock())
+                .map_err(|err| err.to_string())?;
+            return Ok("".into());
+        }
+        parser::Command::Ls(target, path) => {
+            let backup = unlocked_backup(&backups, target, &arguments.password)?;
+            let manifest =
+                manifest::Manifest::from_backup(&backup).map_err(|err| err.to_string())?;
+            let (domain, relative_path) = match path {
+                Some(path) => match path.split_once('/') {
+                    Some((domain, relative_path)) => (Some(domain), relative_path),
+                    None => (Some(path.as_str()), ""),
+                },
+                None => (None, ""),
+            };
+            let depth = match (arguments.tree, arguments.depth) {
+                (_, Some(depth)) => depth,
+                (true, None) => usize::MAX,
+                (false, None) => 1,
+            };
+            let node = browse::list_dir(&manifest, domain, relative_path, depth)
+                .map_err(|err| err.to_string())?;
+            if arguments.tree {
+                browse::print_tree(&node, arguments.long, "");
+            } else {
+                browse::print_list(&node, arguments.long);
+            }
+            return Ok("".into());
+        }
+        parser::Command::Find => {
+            let mut exported = Vec::new();
+            for backup in &backups {
+                let mut backup = backup.clone();
+                if backup.encrypted {
+                    if arguments.password.is_empty() || arguments.extract {
+                        log::warn!(
+                            "Skipping encrypted backup '{}' for '{}'",
+                            backup.path.display(),
+                            backup.serial_number
+                        );
+                        continue;
+                    }
+                    backup.unlock(&arguments.password)?;
+                }
+                let matches = match find::search(&backup, &arguments.query) {
+                    Ok(matches) => matches,
+                    Err(err) => {
+                        log::error!("Failed to search '{}': {}", backup.path.display(), err);
+                        continue;
+                    }
+                };
+                find::print_matches(&backup, &matches);
+                if arguments.extract && !matches.is_empty() {
+                    let plan = fileio::plan_extraction(&backup, matches, &options);
+                    if arguments.export_plan.is_some() {
+                        exported.extend(plan);
+                        continue;
+                    }
+                    log::info!(
+                        "Extracting {} matches for '{}'",
+                        plan.len(),
+                        backup.serial_number
+                    );
+                    let report = fileio::extract_entries(
+                        &backup,
+                        plan,
+                        &options,
+                        arguments.progress,
+                        signal_token(),
+                    )
+                    .map_err(|err| err.to_string())?;
+                    if report.stopped() {
+                        return Err("Extraction stopped before completion".into());
+                    }
+                }
+            }
+            if let Some(path) = &arguments.export_plan {
+                export_plan(path, &exported)?;
+            }
+            return Ok("".into());
+        }
+        parser::Command::Stats(target) => {
+            let backup = unlocked_backup(&backups, target, &arguments.password)?;
+            let stats = stats::collect(&backup).map_err(|err| err.to_string())?;
+            stats::print_stats(&stats, arguments.top);
+            return Ok("".into());
+        }
+        parser::Command::Verify(target) => {
+            let backup = unlocked_backup(&backups, target, &arguments.password)?;
+            log::info!("Verifying backup: '{}'", backup.path.display());
+            let report = verify::verify_backup(&backup).map_err(|err| err.to_string())?;
+            verify::print_report(&report);
+            if !report.is_ok() {
+                return Err(format!(
+                    "Backup '{}' failed verification",
+                    backup.path.display()
+                ));
+            }
+            return Ok("".into());
+        }
+        parser::Command::VerifyOutput(_) => unreachable!("verified before the backups are loaded"),
+        parser::Command::Extract => {}
+    }
 
+    if let Some(path) = &arguments.plan {
+        return replay_plan(&backups, path, &arguments);
+    }
     let mut manifests = Vec::new();
     for backup in backups {
-        let manifest_db_path = backup.path.join("Manifest.db");
-        if manifest_db_path.exists() {
-            manifests.push((backup, manifest_db_path));
+        if !extractable(&backup, arguments.force) {
+            continue;
+        }
+        if backup.manifest_format().is_some() {
+            manifests.push(backup);
+        } else {
+            log::warn!("No manifest found in '{}'", backup.path.display());
         }
     }
     log::info!(
         "Number of manifests staged for extraction: {}",
         manifests.len()
     );
+    if let Some(path) = &arguments.export_plan {
+        let mut exported = Vec::new();
+        for backup in &manifests {
+            match fileio::manifest_plan(backup, &options) {
+                Ok(plan) => exported.extend(plan),
+                Err(err) => {
+                    log::error!("{}", err);
+                    return Err("".into());
+                }
+            }
+        }
+        return export_plan(path, &exported);
+    }
     log::info!("Number of workers assigned: {}", arguments.workers);
-    for (backup, manifest) in manifests {
-        let manifest_id = manifest
+    if arguments.parallel && !arguments.dry_run && manifests.len() > 1 {
+        let start = squire::get_epoch();
+        let reports =
+            fileio::extract_parallel(&manifests, &options, arguments.progress, signal_token());
+        fileio::print_reports(&manifests, &reports);
+        log::info!(
+            "Time taken: {}",
+            squire::convert_seconds((squire::get_epoch() - start) as i64, 1)
+        );
+        if reports
             .iter()
-            .rev()
-            .nth(1)
+            .any(|report| report.as_ref().map_or(true, |report| report.stopped()))
+        {
+            return Err("Extraction cancelled or failed for some backups".into());
+        }
+        return Ok("".into());
+    }
+    for backup in manifests {
+        let manifest_id = backup
+            .path
+            .file_name()
             .unwrap_or_default()
             .to_string_lossy()
             .to_string();
         log::info!("Extracting manifest: '{}'", &manifest_id);
         let start = squire::get_epoch();
-        match fileio::parse_manifest_db(&manifest, &backup, &arguments) {
+        match fileio::parse_manifest_db(&backup, &options, arguments.progress, signal_token()) {
+            Ok(report) if report.stopped() => {
+                return Err("Extraction stopped before completion".into());
+            }
             Ok(_) => {
                 log::info!("Extraction completed for manifest: {:?}", manifest_id);
                 log::info!(
@@ -1,10 +1,12 @@
 fn main() {
     match ios::extractor() {
         Ok(res) => {
-            println!("{}", res);
+            if !res.is_empty() {
+                println!("{}", res);
+            }
         }
         Err(err) => {
-            println!("{}", err);
+            eprintln!("{}", err);
         }
     };
 }
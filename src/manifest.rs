@@ -0,0 +1,511 @@
+use crate::metadata::{self, FileMetadata};
+use crate::{constant, crypto, mbdb, squire};
+use rusqlite::{ffi, params_from_iter, Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::path::Path;
+
+/// Number of rows fetched from the manifest database at a time
+const PAGE_SIZE: usize = 1000;
+
+/// Struct to store a single entry of the manifest
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestEntry {
+    pub file_id: String,
+    pub domain: String,
+    pub relative_path: String,
+    pub flags: i64,
+    pub metadata: Option<FileMetadata>,
+}
+
+impl ManifestEntry {
+    /// Returns `true` if the entry is a regular file
+    pub fn is_file(&self) -> bool {
+        self.flags == 1
+    }
+
+    /// Returns `true` if the entry is a directory
+    pub fn is_directory(&self) -> bool {
+        self.flags == 2
+    }
+
+    /// Returns `true` if the entry is a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        self.flags == 4
+    }
+
+    /// Returns the size of the file as recorded in the metadata, or `0` if unavailable
+    pub fn size(&self) -> u64 {
+        self.metadata.as_ref().map_or(0, |meta| meta.size)
+    }
+}
+
+/// Enum to represent the underlying source of the manifest
+enum Source {
+    Database(Connection),
+    Mbdb(Vec<ManifestEntry>),
+}
+
+/// Struct to read the manifest of a backup
+///
+/// Opens `Manifest.db` read-only, or parses the legacy `Manifest.mbdb` for backups from iOS 9 and earlier.
+pub struct Manifest {
+    source: Source,
+}
+
+impl Manifest {
+    /// Opens the manifest of a backup
+    ///
+    /// # Arguments
+    ///
+    /// * `backup_path` - The path to the backup directory
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - The `Manifest` of the backup
+    /// * `Err` - If no manifest is present or it cannot be opened
+    pub fn open(backup_path: &Path) -> Result<Manifest, Box<dyn std::error::Error>> {
+        let manifest_db = backup_path.join("Manifest.db");
+        let manifest_mbdb = backup_path.join("Manifest.mbdb");
+        if manifest_db.is_file() {
+            Manifest::open_database(&manifest_db)
+        } else if manifest_mbdb.is_file() {
+            Ok(Manifest {
+                source: Source::Mbdb(mbdb::parse(&manifest_mbdb)?),
+            })
+        } else {
+            Err(format!("No manifest found in '{}'", backup_path.display()).into())
+        }
+    }
+
+    /// Opens a manifest database read-only
+    ///
+    /// # Arguments
+    ///
+    /// * `manifest_db_path` - The path to the manifest database
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - The `Manifest` of the backup
+    /// * `Err` - If the database cannot be opened
+    pub fn open_database(manifest_db_path: &Path) -> Result<Manifest, Box<dyn std::error::Error>> {
+        let conn = Connection::open_with_flags(manifest_db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Ok(Manifest {
+            source: Source::Database(conn),
+        })
+    }
+
+    /// Opens a manifest database held in memory read-only
+    ///
+    /// # Arguments
+    ///
+    /// * `database` - The contents of the manifest database
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - The `Manifest` of the backup
+    /// * `Err` - If the contents are not a valid database
+    pub fn open_memory(database: &[u8]) -> Result<Manifest, Box<dyn std::error::Error>> {
+        let conn = Connection::open_in_memory()?;
+        let size = database.len();
+        // SQLite takes ownership of the buffer and frees it when the connection is closed, or on failure
+        let code = unsafe {
+            let buffer = ffi::sqlite3_malloc64(size.max(1) as u64) as *mut u8;
+            if buffer.is_null() {
+                return Err("Failed to allocate memory for the manifest database".into());
+            }
+            std::ptr::copy_nonoverlapping(database.as_ptr(), buffer, size);
+            ffi::sqlite3_deserialize(
+                conn.handle(),
+                c"main".as_ptr(),
+                buffer,
+                size as i64,
+                size as i64,
+                (ffi::SQLITE_DESERIALIZE_FREEONCLOSE | ffi::SQLITE_DESERIALIZE_READONLY) as u32,
+            )
+        };
+        if code != ffi::SQLITE_OK {
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(code), None).into());
+        }
+        // Fail early if the contents are not a database, e.g. when decrypted with the wrong key
+        conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+        Ok(Manifest {
+            source: Source::Database(conn),
+        })
+    }
+
+    /// Opens the manifest of a backup, decrypting it first if the backup is encrypted
+    ///
+    /// # Arguments
+    ///
+    /// * `backup` - The backup information, unlocked with `Backup::unlock` if it is encrypted
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - The `Manifest` of the backup
+    /// * `Err` - If the manifest cannot be opened or the backup is locked
+    pub fn from_backup(backup: &constant::Backup) -> Result<Manifest, Box<dyn std::error::Error>> {
+        if !backup.encrypted || backup.manifest_format() != Some(constant::ManifestFormat::Database)
+        {
+            return Manifest::open(&backup.path);
+        }
+        let keys = backup.keys.as_ref().ok_or(format!(
+            "Backup '{}' is encrypted, a password is required",
+            backup.path.display()
+        ))?;
+        let decrypted = crypto::decrypt_manifest_db(&backup.path.join("Manifest.db"), keys)?;
+        Manifest::open_memory(&decrypted)
+    }
+
+    /// Finds a single entry by its domain and relative path
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - The domain of the file, e.g. `HomeDomain`
+    /// * `relative_path` - The path of the file within the domain, e.g. `Library/SMS/sms.db`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - An `Option` containing the matching `ManifestEntry`
+    /// * `Err` - If the manifest cannot be queried
+    pub fn find(
+        &self,
+        domain: &str,
+        relative_path: &str,
+    ) -> rusqlite::Result<Option<ManifestEntry>> {
+        self.entries()
+            .domain(domain)
+            .path(relative_path.trim_matches('/'))
+            .next()
+            .transpose()
+    }
+
+    /// Returns the distinct domains in the manifest, sorted by name
+    pub fn domains(&self) -> rusqlite::Result<Vec<String>> {
+        match &self.source {
+            Source::Database(conn) => {
+                let mut stmt = conn.prepare("SELECT DISTINCT domain FROM Files ORDER BY domain")?;
+                let rows = stmt.query_map([], |row| row.get(0))?;
+                rows.collect()
+            }
+            Source::Mbdb(entries) => {
+                let domains: BTreeSet<String> =
+                    entries.iter().map(|entry| entry.domain.clone()).collect();
+                Ok(domains.into_iter().collect())
+            }
+        }
+    }
+
+    /// Returns an iterator over all the entries in the manifest
+    pub fn entries(&self) -> Entries<'_> {
+        Entries {
+            manifest: self,
+            filter: Filter::default(),
+            buffer: VecDeque::new(),
+            cursor: None,
+            index: 0,
+            exhausted: false,
+        }
+    }
+}
+
+/// Struct to store the filters applied to the manifest entries
+#[derive(Default, Clone)]
+struct Filter {
+    files_only: bool,
+    domain: Option<String>,
+    domain_prefix: Option<String>,
+    path: Option<String>,
+    path_prefix: Option<String>,
+    extensions: Option<Vec<String>>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &ManifestEntry) -> bool {
+        if self.files_only && !entry.is_file() {
+            return false;
+        }
+        if let Some(domain) = &self.domain {
+            if &entry.domain != domain {
+                return false;
+            }
+        }
+        if let Some(prefix) = &self.domain_prefix {
+            if !entry.domain.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if let Some(path) = &self.path {
+            if &entry.relative_path != path {
+                return false;
+            }
+        }
+        if let Some(prefix) = &self.path_prefix {
+            if !entry.relative_path.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if let Some(extensions) = &self.extensions {
+            let lower = entry.relative_path.to_lowercase();
+            if !extensions
+                .iter()
+                .any(|ext| lower.ends_with(&format!(".{}", ext)))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Function to escape the wildcards of a `LIKE` pattern
+///
+/// # Arguments
+///
+/// * `value` - The literal value to match
+///
+/// # Returns
+///
+/// A `String` with `%`, `_` and `\` escaped with a backslash
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Iterator over the entries of a manifest
+///
+/// Rows from the manifest database are fetched in pages ordered by the file ID,
+/// so memory usage stays constant regardless of the size of the backup.
+pub struct Entries<'a> {
+    manifest: &'a Manifest,
+    filter: Filter,
+    buffer: VecDeque<ManifestEntry>,
+    cursor: Option<String>,
+    index: usize,
+    exhausted: bool,
+}
+
+impl Entries<'_> {
+    /// Restricts the entries to regular files
+    pub fn files_only(mut self) -> Self {
+        self.filter.files_only = true;
+        self
+    }
+
+    /// Restricts the entries to the given domain, e.g. `CameraRollDomain`
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.filter.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Restricts the entries to domains starting with the given prefix, e.g. `AppDomain-`
+    pub fn domain_prefix(mut self, prefix: &str) -> Self {
+        self.filter.domain_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Restricts the entries to the given relative path
+    pub fn path(mut self, relative_path: &str) -> Self {
+        self.filter.path = Some(relative_path.to_string());
+        self
+    }
+
+    /// Restricts the entries to relative paths starting with the given prefix
+    pub fn path_prefix(mut self, prefix: &str) -> Self {
+        self.filter.path_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Restricts the entries to the given file extensions (case-insensitive, without the dot)
+    pub fn extensions(mut self, extensions: &[&str]) -> Self {
+        self.filter.extensions = Some(extensions.iter().map(|ext| ext.to_lowercase()).collect());
+        self
+    }
+
+    /// Restricts the entries to media files
+    pub fn media(self) -> Self {
+        self.files_only().extensions(&squire::MEDIA_EXTENSIONS)
+    }
+
+    /// Fetches the next page of rows from the manifest database
+    fn fetch(&mut self, conn: &Connection) -> rusqlite::Result<()> {
+        let mut conditions = vec!["fileID > ?".to_string()];
+        let mut params = vec![self.cursor.clone().unwrap_or_default()];
+        if self.filter.files_only {
+            conditions.push("flags = 1".to_string());
+        }
+        if let Some(domain) = &self.filter.domain {
+            conditions.push("domain = ?".to_string());
+            params.push(domain.clone());
+        }
+        if let Some(prefix) = &self.filter.domain_prefix {
+            conditions.push("substr(domain, 1, length(?)) = ?".to_string());
+            params.push(prefix.clone());
+            params.push(prefix.clone());
+        }
+        if let Some(path) = &self.filter.path {
+            conditions.push("relativePath = ?".to_string());
+            params.push(path.clone());
+        }
+        if let Some(prefix) = &self.filter.path_prefix {
+            conditions.push("substr(relativePath, 1, length(?)) = ?".to_string());
+            params.push(prefix.clone());
+            params.push(prefix.clone());
+        }
+        // Filtered in the query, so the metadata is only decoded for the rows that match
+        if let Some(extensions) = &self.filter.extensions {
+            let likes = vec!["relativePath LIKE ? ESCAPE '\\'"; extensions.len()];
+            conditions.push(format!("({})", likes.join(" OR ")));
+            params.extend(
+                extensions
+                    .iter()
+                    .map(|ext| format!("%.{}", escape_like(ext))),
+            );
+        }
+        let mut stmt = conn.prepare(&format!(
+            "SELECT fileID, domain, relativePath, flags, file FROM Files WHERE {} ORDER BY fileID LIMIT {}",
+            conditions.join(" AND "),
+            PAGE_SIZE
+        ))?;
+        let rows = stmt.query_map(params_from_iter(params), |row| {
+            let blob: Option<Vec<u8>> = row.get(4)?;
+            Ok(ManifestEntry {
+                file_id: row.get(0)?,
+                domain: row.get(1)?,
+                relative_path: row.get(2)?,
+                flags: row.get(3)?,
+                metadata: blob.as_deref().and_then(metadata::decode),
+            })
+        })?;
+        let mut fetched = 0;
+        for row in rows {
+            let entry = row?;
+            fetched += 1;
+            self.cursor = Some(entry.file_id.clone());
+            if self.filter.matches(&entry) {
+                self.buffer.push_back(entry);
+            }
+        }
+        if fetched < PAGE_SIZE {
+            self.exhausted = true;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Entries<'_> {
+    type Item = rusqlite::Result<ManifestEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &self.manifest.source {
+            Source::Mbdb(entries) => {
+                while let Some(entry) = entries.get(self.index) {
+                    self.index += 1;
+                    if self.filter.matches(entry) {
+                        return Some(Ok(entry.clone()));
+                    }
+                }
+                None
+            }
+            Source::Database(conn) => loop {
+                if let Some(entry) = self.buffer.pop_front() {
+                    return Some(Ok(entry));
+                }
+                if self.exhausted {
+                    return None;
+                }
+                if let Err(err) = self.fetch(conn) {
+                    self.exhausted = true;
+                    return Some(Err(err));
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(rows: &[(&str, &str, &str, i64)]) -> (tempfile::TempDir, Manifest) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Manifest.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Files (fileID TEXT PRIMARY KEY, domain TEXT, relativePath TEXT, flags INTEGER, file BLOB);",
+        )
+        .unwrap();
+        for (file_id, domain, relative_path, flags) in rows {
+            conn.execute(
+                "INSERT INTO Files VALUES (?, ?, ?, ?, NULL)",
+                rusqlite::params![file_id, domain, relative_path, flags],
+            )
+            .unwrap();
+        }
+        drop(conn);
+        let manifest = Manifest::open(dir.path()).unwrap();
+        (dir, manifest)
+    }
+
+    #[test]
+    fn opens_database_from_memory() {
+        let (dir, _manifest) = manifest(&[("01", "HomeDomain", "Library/a.jpg", 1)]);
+        let database = std::fs::read(dir.path().join("Manifest.db")).unwrap();
+        let manifest = Manifest::open_memory(&database).unwrap();
+        assert_eq!(manifest.domains().unwrap(), ["HomeDomain"]);
+        assert!(manifest
+            .find("HomeDomain", "Library/a.jpg")
+            .unwrap()
+            .is_some());
+        assert!(Manifest::open_memory(b"not a database").is_err());
+    }
+
+    #[test]
+    fn filters_extensions_in_query() {
+        let (_dir, manifest) = manifest(&[
+            ("01", "CameraRollDomain", "Media/DCIM/IMG_0001.JPG", 1),
+            ("02", "CameraRollDomain", "Media/DCIM/IMG_0002.mov", 1),
+            ("03", "CameraRollDomain", "Media/DCIM/IMG_0003.txt", 1),
+            ("04", "CameraRollDomain", "Media/DCIM/IMG_0004xjpg", 1),
+            ("05", "CameraRollDomain", "Media/DCIM", 2),
+            ("06", "AppDomain-com.example", "Documents/photo.png", 1),
+        ]);
+        let ids: Vec<String> = manifest
+            .entries()
+            .media()
+            .map(|entry| entry.unwrap().file_id)
+            .collect();
+        assert_eq!(ids, ["01", "02", "06"]);
+    }
+
+    #[test]
+    fn escapes_like_wildcards() {
+        let (_dir, manifest) = manifest(&[
+            ("01", "HomeDomain", "Library/a.x_y", 1),
+            ("02", "HomeDomain", "Library/b.xzy", 1),
+        ]);
+        let ids: Vec<String> = manifest
+            .entries()
+            .extensions(&["x_y"])
+            .map(|entry| entry.unwrap().file_id)
+            .collect();
+        assert_eq!(ids, ["01"]);
+    }
+
+    #[test]
+    fn filters_domain_prefix_in_query() {
+        let (_dir, manifest) = manifest(&[
+            ("01", "AppDomain-com.example", "Documents/a.jpg", 1),
+            ("02", "AppDomainGroup-group.example", "b.jpg", 1),
+            ("03", "HomeDomain", "c.jpg", 1),
+        ]);
+        let ids: Vec<String> = manifest
+            .entries()
+            .domain_prefix("AppDomain-")
+            .map(|entry| entry.unwrap().file_id)
+            .collect();
+        assert_eq!(ids, ["01"]);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::manifest::ManifestEntry;
+use crate::metadata::FileMetadata;
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Struct to read big-endian values from the `Manifest.mbdb` buffer
+struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8]> {
+        let end = self.offset + length;
+        if end > self.buffer.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Manifest.mbdb is truncated at offset {}", self.offset),
+            ));
+        }
+        let bytes = &self.buffer[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, length: usize) -> Result<u64> {
+        Ok(self
+            .take(length)?
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | *byte as u64))
+    }
+
+    /// Strings are prefixed with a 2 byte length, where `0xFFFF` represents an empty value
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        match self.uint(2)? {
+            0xFFFF => Ok(Vec::new()),
+            length => Ok(self.take(length as usize)?.to_vec()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.bytes()?).to_string())
+    }
+}
+
+/// Function to compute the file ID of a legacy backup entry
+///
+/// # Arguments
+///
+/// * `domain` - The domain of the file
+/// * `relative_path` - The relative path of the file within the domain
+///
+/// # Returns
+///
+/// A `String` with the hex encoded SHA-1 of `<domain>-<relative_path>`
+pub fn file_id(domain: &str, relative_path: &str) -> String {
+    let digest = Sha1::digest(format!("{}-{}", domain, relative_path).as_bytes());
+    format!("{:x}", digest)
+}
+
+/// Function to convert the file mode into the `flags` used by the manifest database
+///
+/// # Arguments
+///
+/// * `mode` - The file mode
+///
+/// # Returns
+///
+/// An `i64` with `1` for files, `2` for directories, `4` for symlinks and `0` otherwise
+fn flags(mode: u32) -> i64 {
+    match mode & 0xF000 {
+        0x8000 => 1,
+        0x4000 => 2,
+        0xA000 => 4,
+        _ => 0,
+    }
+}
+
+/// Function to parse the legacy `Manifest.mbdb` file used by backups from iOS 9 and earlier
+///
+/// # Arguments
+///
+/// * `path` - The path to the `Manifest.mbdb` file
+///
+/// # Returns
+///
+/// * `Ok` - A vector of `ManifestEntry` structs, with the `file_id` computed as the SHA-1 of `<domain>-<relative_path>`
+/// * `Err` - If the file cannot be read or is not a valid `Manifest.mbdb`
+pub fn parse(path: &Path) -> Result<Vec<ManifestEntry>> {
+    let buffer = fs::read(path)?;
+    let mut reader = Reader {
+        buffer: &buffer,
+        offset: 0,
+    };
+    if reader.take(4)? != b"mbdb" {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("'{}' is not a valid Manifest.mbdb", path.display()),
+        ));
+    }
+    // Major and minor version of the format
+    reader.take(2)?;
+
+    let mut entries = Vec::new();
+    while reader.offset < buffer.len() {
+        let domain = reader.string()?;
+        let relative_path = reader.string()?;
+        let target = reader.string()?;
+        let _data_hash = reader.bytes()?;
+        let encryption_key = reader.bytes()?;
+        let mode = reader.uint(2)? as u32;
+        let inode = reader.uint(8)?;
+        let user_id = reader.uint(4)? as u32;
+        let group_id = reader.uint(4)? as u32;
+        let last_modified = reader.uint(4)? as i64;
+        let _last_accessed = reader.uint(4)?;
+        let last_status_change = reader.uint(4)? as i64;
+        let size = reader.uint(8)?;
+        let protection_class = reader.uint(1)? as u32;
+        let properties = reader.uint(1)?;
+        for _ in 0..properties {
+            reader.bytes()?;
+            reader.bytes()?;
+        }
+        entries.push(ManifestEntry {
+            file_id: file_id(&domain, &relative_path),
+            flags: flags(mode),
+            metadata: Some(FileMetadata {
+                size,
+                mode,
+                inode,
+                user_id,
+                group_id,
+                last_modified,
+                last_status_change,
+                birth: None,
+                protection_class,
+                target: (!target.is_empty()).then_some(target),
+                encryption_key: (!encryption_key.is_empty()).then_some(encryption_key),
+            }),
+            domain,
+            relative_path,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Function to append a length prefixed string to a record
+    fn push_string(record: &mut Vec<u8>, value: &[u8]) {
+        match value.is_empty() {
+            true => record.extend_from_slice(&0xFFFFu16.to_be_bytes()),
+            false => {
+                record.extend_from_slice(&(value.len() as u16).to_be_bytes());
+                record.extend_from_slice(value);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_hand_built_record() {
+        let mut buffer = b"mbdb\x05\x00".to_vec();
+        push_string(&mut buffer, b"CameraRollDomain");
+        push_string(&mut buffer, b"Media/DCIM/100APPLE/IMG_0001.JPG");
+        push_string(&mut buffer, b"");
+        push_string(&mut buffer, b"");
+        push_string(&mut buffer, b"");
+        buffer.extend_from_slice(&0x81A4u16.to_be_bytes());
+        buffer.extend_from_slice(&42u64.to_be_bytes());
+        buffer.extend_from_slice(&501u32.to_be_bytes());
+        buffer.extend_from_slice(&20u32.to_be_bytes());
+        buffer.extend_from_slice(&1_400_000_001u32.to_be_bytes());
+        buffer.extend_from_slice(&1_400_000_002u32.to_be_bytes());
+        buffer.extend_from_slice(&1_400_000_003u32.to_be_bytes());
+        buffer.extend_from_slice(&1234u64.to_be_bytes());
+        buffer.push(3);
+        buffer.push(1);
+        push_string(&mut buffer, b"com.apple.assetsd.UUID");
+        push_string(&mut buffer, b"value");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Manifest.mbdb");
+        fs::write(&path, &buffer).unwrap();
+        let entries = parse(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.domain, "CameraRollDomain");
+        assert_eq!(entry.relative_path, "Media/DCIM/100APPLE/IMG_0001.JPG");
+        assert_eq!(
+            entry.file_id,
+            file_id("CameraRollDomain", "Media/DCIM/100APPLE/IMG_0001.JPG")
+        );
+        assert_eq!(entry.flags, 1);
+        let metadata = entry.metadata.as_ref().unwrap();
+        assert_eq!(metadata.size, 1234);
+        assert_eq!(metadata.inode, 42);
+        assert_eq!(metadata.user_id, 501);
+        assert_eq!(metadata.group_id, 20);
+        assert_eq!(metadata.last_modified, 1_400_000_001);
+        assert_eq!(metadata.last_status_change, 1_400_000_003);
+        assert_eq!(metadata.birth, None);
+        assert_eq!(metadata.protection_class, 3);
+        assert!(metadata.target.is_none());
+        assert!(metadata.encryption_key.is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_record() {
+        let mut buffer = b"mbdb\x05\x00".to_vec();
+        push_string(&mut buffer, b"CameraRollDomain");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Manifest.mbdb");
+        fs::write(&path, &buffer).unwrap();
+        let err = parse(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_invalid_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Manifest.mbdb");
+        fs::write(&path, b"sqlite").unwrap();
+        assert_eq!(parse(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
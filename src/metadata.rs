@@ -0,0 +1,177 @@
+use plist::{Dictionary, Value};
+use serde::Serialize;
+use std::io::Cursor;
+
+/// Struct to store the file metadata archived in the `file` column of the manifest database
+///
+/// The column holds an `MBFile` object serialized with `NSKeyedArchiver` as a binary plist.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FileMetadata {
+    pub size: u64,
+    pub mode: u32,
+    pub inode: u64,
+    pub user_id: u32,
+    pub group_id: u32,
+    pub last_modified: i64,
+    pub last_status_change: i64,
+    /// Not recorded by the legacy `Manifest.mbdb`
+    pub birth: Option<i64>,
+    pub protection_class: u32,
+    pub target: Option<String>,
+    #[serde(skip)]
+    pub encryption_key: Option<Vec<u8>>,
+}
+
+/// Function to resolve an `NSKeyedArchiver` reference into the object it points to
+///
+/// # Arguments
+///
+/// * `objects` - The `$objects` array of the archive
+/// * `value` - The value that may be a reference
+///
+/// # Returns
+///
+/// An `Option` containing the referenced value, or the value itself if it is not a reference
+fn resolve<'a>(objects: &'a [Value], value: &'a Value) -> Option<&'a Value> {
+    match value.as_uid() {
+        Some(uid) => objects.get(uid.get() as usize),
+        None => Some(value),
+    }
+}
+
+/// Function to retrieve an integer from the archived object
+///
+/// # Arguments
+///
+/// * `object` - The archived `MBFile` object
+/// * `key` - The key to retrieve the value for
+///
+/// # Returns
+///
+/// An `i64` with the value, or `0` if the key is not present
+fn integer(object: &Dictionary, key: &str) -> i64 {
+    object
+        .get(key)
+        .and_then(|v| v.as_signed_integer())
+        .unwrap_or_default()
+}
+
+/// Function to decode the file metadata from the manifest database
+///
+/// # Arguments
+///
+/// * `blob` - The binary plist stored in the `file` column
+///
+/// # Returns
+///
+/// An `Option` containing the decoded `FileMetadata`
+pub fn decode(blob: &[u8]) -> Option<FileMetadata> {
+    let archive = Value::from_reader(Cursor::new(blob)).ok()?;
+    let archive = archive.as_dictionary()?;
+    let objects = archive.get("$objects")?.as_array()?;
+    let root = archive.get("$top")?.as_dictionary()?.get("root")?;
+    let object = resolve(objects, root)?.as_dictionary()?;
+    let target = object
+        .get("Target")
+        .and_then(|v| resolve(objects, v))
+        .and_then(Value::as_string)
+        .map(String::from);
+    // Encrypted backups archive the wrapped file key as `NSData`
+    let encryption_key = object
+        .get("EncryptionKey")
+        .and_then(|v| resolve(objects, v))
+        .and_then(|v| match v {
+            Value::Data(data) => Some(data.clone()),
+            Value::Dictionary(dict) => dict.get("NS.data")?.as_data().map(<[u8]>::to_vec),
+            _ => None,
+        });
+    Some(FileMetadata {
+        size: integer(object, "Size").max(0) as u64,
+        mode: integer(object, "Mode") as u32,
+        inode: integer(object, "InodeNumber").max(0) as u64,
+        user_id: integer(object, "UserID") as u32,
+        group_id: integer(object, "GroupID") as u32,
+        last_modified: integer(object, "LastModified"),
+        last_status_change: integer(object, "LastStatusChange"),
+        birth: object.get("Birth").and_then(Value::as_signed_integer),
+        protection_class: integer(object, "ProtectionClass") as u32,
+        target,
+        encryption_key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plist::Uid;
+
+    /// Function to archive an `MBFile` object the way `NSKeyedArchiver` does
+    fn archive(object: Dictionary, extra: Vec<Value>) -> Vec<u8> {
+        let mut objects = vec![
+            Value::String("$null".to_string()),
+            Value::Dictionary(object),
+        ];
+        objects.extend(extra);
+        let mut top = Dictionary::new();
+        top.insert("root".to_string(), Value::Uid(Uid::new(1)));
+        let mut archive = Dictionary::new();
+        archive.insert("$objects".to_string(), Value::Array(objects));
+        archive.insert("$top".to_string(), Value::Dictionary(top));
+        let mut blob = Vec::new();
+        Value::Dictionary(archive)
+            .to_writer_binary(&mut blob)
+            .unwrap();
+        blob
+    }
+
+    #[test]
+    fn decodes_archived_object() {
+        let mut object = Dictionary::new();
+        object.insert("Size".to_string(), Value::Integer(2048.into()));
+        object.insert("Mode".to_string(), Value::Integer(0o100644.into()));
+        object.insert(
+            "LastModified".to_string(),
+            Value::Integer(1_600_000_000.into()),
+        );
+        object.insert(
+            "LastStatusChange".to_string(),
+            Value::Integer(1_600_000_001.into()),
+        );
+        object.insert("Birth".to_string(), Value::Integer(1_500_000_000.into()));
+        object.insert("ProtectionClass".to_string(), Value::Integer(4.into()));
+        object.insert("Target".to_string(), Value::Uid(Uid::new(2)));
+        let mut key = Dictionary::new();
+        key.insert("NS.data".to_string(), Value::Data(vec![1, 2, 3]));
+        object.insert("EncryptionKey".to_string(), Value::Uid(Uid::new(3)));
+        let blob = archive(
+            object,
+            vec![
+                Value::String("../target".to_string()),
+                Value::Dictionary(key),
+            ],
+        );
+
+        let metadata = decode(&blob).unwrap();
+        assert_eq!(metadata.size, 2048);
+        assert_eq!(metadata.mode, 0o100644);
+        assert_eq!(metadata.last_modified, 1_600_000_000);
+        assert_eq!(metadata.last_status_change, 1_600_000_001);
+        assert_eq!(metadata.birth, Some(1_500_000_000));
+        assert_eq!(metadata.protection_class, 4);
+        assert_eq!(metadata.target.as_deref(), Some("../target"));
+        assert_eq!(metadata.encryption_key, Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn missing_keys_default() {
+        let metadata = decode(&archive(Dictionary::new(), Vec::new())).unwrap();
+        assert_eq!(metadata.size, 0);
+        assert_eq!(metadata.birth, None);
+        assert!(metadata.target.is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_blob() {
+        assert!(decode(b"not a plist").is_none());
+    }
+}
@@ -0,0 +1,44 @@
+use crate::constant::Backup;
+use crate::fileio::{ExtractionReport, PlannedFile};
+
+/// Trait to observe an extraction, implemented by the progress bar and by library users
+///
+/// All the callbacks are invoked from the thread that called the extraction, in the order the files complete.
+/// Every method has an empty default implementation, so observers only implement the events they need.
+pub trait ExtractionObserver {
+    /// Called once before the first file is extracted
+    ///
+    /// # Arguments
+    ///
+    /// * `backup` - The backup being extracted
+    /// * `files` - Number of files to be extracted
+    /// * `bytes` - Total size of the files to be extracted
+    fn on_start(&mut self, _backup: &Backup, _files: usize, _bytes: u64) {}
+
+    /// Called after a file was extracted
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The extracted file
+    fn on_file_done(&mut self, _file: &PlannedFile) {}
+
+    /// Called after a file failed to be extracted
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The planned file
+    /// * `error` - The error encountered
+    fn on_file_error(&mut self, _file: &PlannedFile, _error: &std::io::Error) {}
+
+    /// Called once after the extraction has finished or was cancelled
+    ///
+    /// # Arguments
+    ///
+    /// * `report` - The extraction report
+    fn on_finish(&mut self, _report: &ExtractionReport) {}
+}
+
+/// Observer that ignores every event
+pub struct NoopObserver;
+
+impl ExtractionObserver for NoopObserver {}
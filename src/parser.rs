@@ -1,25 +1,77 @@
-use crate::{constant, squire};
+use crate::constant::{Organizer, ProgressMode};
+use crate::{checksum, constant, fileio, find, plan, squire};
+use chrono::{Local, NaiveDate};
+use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::path::PathBuf;
 
-/// Enum to represent the different ways to organize the extracted files.
-#[derive(Debug, Clone, Copy)]
-pub enum Organizer {
-    Type,
-    Size,
-    Root,
-    Auto,
+/// Enum to represent the sub-commands supported by the CLI.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Extract,
+    Inspect(String),
+    Verify(String),
+    VerifyOutput(Option<PathBuf>),
+    Cat(String, String),
+    Ls(String, Option<String>),
+    Find,
+    Stats(String),
 }
 
 /// Struct to construct the commandline arguments.
 pub struct ArgConfig {
+    pub command: Command,
     pub list: bool,
+    pub no_size: bool,
     pub all: bool,
+    pub force: bool,
     pub debug: bool,
     pub serial_numbers: Vec<String>,
     pub backup_dir: PathBuf,
     pub output_dir: PathBuf,
     pub workers: usize,
     pub organize: Organizer,
+    pub password: String,
+    pub long: bool,
+    pub tree: bool,
+    pub depth: Option<usize>,
+    pub query: find::Query,
+    pub extract: bool,
+    pub top: usize,
+    pub dry_run: bool,
+    pub export_plan: Option<PathBuf>,
+    pub plan: Option<PathBuf>,
+    pub resume: bool,
+    pub sync: bool,
+    pub delete: bool,
+    pub link: bool,
+    pub progress: ProgressMode,
+    pub parallel: bool,
+    pub max_bandwidth: Option<u64>,
+    pub max_iops: Option<u64>,
+    pub checksum: Option<checksum::Algorithm>,
+}
+
+impl ArgConfig {
+    /// Function to map the command line arguments into the options of an extraction
+    ///
+    /// # Returns
+    ///
+    /// An `ExtractOptions` struct
+    pub fn extract_options(&self) -> fileio::ExtractOptions {
+        fileio::ExtractOptions::default()
+            .output_dir(&self.output_dir)
+            .organize(self.organize)
+            .workers(self.workers)
+            .link(self.link)
+            .resume(self.resume)
+            .sync(self.sync)
+            .delete(self.delete)
+            .dry_run(self.dry_run)
+            .checksum(self.checksum)
+            .max_bandwidth(self.max_bandwidth)
+            .max_iops(self.max_iops)
+    }
 }
 
 /// Function to print an error message and exit when a value is missing.
@@ -27,7 +79,7 @@ pub struct ArgConfig {
 /// # Arguments
 ///
 /// * `key` - The key that requires a value
-fn missing_value(key: &str) {
+fn missing_value(key: &str) -> ! {
     println!("ERROR: '{}' flag requires a value.", key);
     std::process::exit(1)
 }
@@ -70,19 +122,205 @@ fn default_output_directory() -> PathBuf {
     PathBuf::from(output_str)
 }
 
+/// Function to construct the sub-command from the positional arguments.
+///
+/// # Arguments
+///
+/// * `name` - Name of the sub-command
+/// * `positional` - Positional arguments that followed the sub-command
+///
+/// # Returns
+///
+/// The `Command` to be executed
+fn build_command(name: &str, positional: &[String]) -> Command {
+    match name {
+        "inspect" => match positional {
+            [target] => Command::Inspect(target.clone()),
+            _ => {
+                println!("ERROR: 'inspect' requires exactly one serial number or UDID");
+                std::process::exit(1)
+            }
+        },
+        "cat" => match positional {
+            [target, path] => Command::Cat(target.clone(), path.clone()),
+            _ => {
+                println!("ERROR: 'cat' requires a serial number or UDID and a Domain/relativePath");
+                std::process::exit(1)
+            }
+        },
+        "ls" => match positional {
+            [target] => Command::Ls(target.clone(), None),
+            [target, path] => Command::Ls(target.clone(), Some(path.clone())),
+            _ => {
+                println!("ERROR: 'ls' requires a serial number or UDID and an optional Domain/relativePath");
+                std::process::exit(1)
+            }
+        },
+        "find" => match positional {
+            [] => Command::Find,
+            _ => {
+                println!(
+                    "ERROR: 'find' takes its filters as flags, use --serial to select backups"
+                );
+                std::process::exit(1)
+            }
+        },
+        "stats" => match positional {
+            [target] => Command::Stats(target.clone()),
+            _ => {
+                println!("ERROR: 'stats' requires exactly one serial number or UDID");
+                std::process::exit(1)
+            }
+        },
+        "verify-output" => match positional {
+            [] => Command::VerifyOutput(None),
+            [directory] => Command::VerifyOutput(Some(PathBuf::from(directory))),
+            _ => {
+                println!("ERROR: 'verify-output' takes an optional output directory");
+                std::process::exit(1)
+            }
+        },
+        "verify" => match positional {
+            [target] => Command::Verify(target.clone()),
+            _ => {
+                println!("ERROR: 'verify' requires exactly one serial number or UDID");
+                std::process::exit(1)
+            }
+        },
+        _ => {
+            println!("\nERROR: Unknown command: {}\n\n{}", name, helper());
+            std::process::exit(1)
+        }
+    }
+}
+
+/// Function to construct the search query from the `find` filters.
+///
+/// # Arguments
+///
+/// * `filters` - The filter flags mapped to their values
+///
+/// # Returns
+///
+/// The `Query` to search the backups with
+fn build_query(filters: &HashMap<String, String>) -> find::Query {
+    let invalid = |key: &str, value: &String| -> ! {
+        println!("ERROR: Invalid value '{}' for '{}'", value, key);
+        std::process::exit(1)
+    };
+    let pattern = |key: &str, glob: bool| {
+        filters.get(key).map(|value| {
+            let result = if glob {
+                find::glob(value)
+            } else {
+                regex::Regex::new(value)
+            };
+            result.unwrap_or_else(|_| invalid(key, value))
+        })
+    };
+    let size = |key: &str| {
+        filters
+            .get(key)
+            .map(|value| squire::parse_size(value).unwrap_or_else(|| invalid(key, value)))
+    };
+    let date = |key: &str| {
+        filters.get(key).map(|value| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .and_then(|datetime| datetime.and_local_timezone(Local).earliest())
+                .map(|datetime| datetime.timestamp())
+                .unwrap_or_else(|| invalid(key, value))
+        })
+    };
+    if filters.contains_key("--path") && filters.contains_key("--regex") {
+        println!("ERROR: '--path' and '--regex' cannot be used together");
+        std::process::exit(1)
+    }
+    find::Query {
+        name: pattern("--name", true),
+        path: pattern("--path", true).or(pattern("--regex", false)),
+        domain: pattern("--domain", true),
+        min_size: size("--min-size"),
+        max_size: size("--max-size"),
+        after: date("--after"),
+        before: date("--before"),
+    }
+}
+
+/// Function to validate the path of a plan file
+///
+/// # Arguments
+///
+/// * `key` - The argument name
+/// * `value` - The value given for the argument
+/// * `exists` - Flag to require the plan file to exist
+///
+/// # Returns
+///
+/// An `Option` containing the path to the plan file, if the argument was given
+fn plan_path(key: &str, value: String, exists: bool) -> Option<PathBuf> {
+    if value.is_empty() {
+        return None;
+    }
+    let path = PathBuf::from(value);
+    if plan::format(&path).is_none() {
+        println!("ERROR: '{}' must be a .json or .csv file", key);
+        std::process::exit(1)
+    }
+    if exists && !path.exists() {
+        println!("ERROR: Plan file '{}' does not exist!", path.display());
+        std::process::exit(1)
+    }
+    Some(path)
+}
+
 /// Helper function to print the command-line arguments.
 ///
 /// # Returns
 ///
 /// A `String` containing the command-line arguments
 fn helper() -> String {
-    "ios crate takes the following arguments\n\n\
+    "ios crate takes the following commands\n\n\
+    \tinspect <serial|udid>: Print the device, backup and manifest details of a backup.\n\
+    \tverify <serial|udid>: Verify the files on disk against the manifest of a backup.\n\
+    \tverify-output [directory]: Re-hash extracted files against the checksums written with --checksum.\n\
+    \tcat <serial|udid> <Domain/relativePath>: Stream a single file from a backup to stdout.\n\
+    \tls <serial|udid> [Domain/relativePath]: List the domains and directories within a backup.\n\
+    \tfind: Search files across all backups, or the ones given with --serial.\n\
+    \tstats <serial|udid>: Print the disk usage of a backup by category, domain, app and extension.\n\n\
+    ios crate takes the following arguments\n\n\
     \t--version: Print project version.\n\n\
     \t--list: List the available backups.\n\
+    \t--no-size: Skip computing the backup size while listing.\n\
     \t--debug: Enable debug level logging.\n\
     \t--all: Extract all available backups.\n\
+    \t--force: Extract backups even if they are flagged as in progress or corrupt.\n\
     \t--serial: Initiate backup extraction for given serial number(s).\n\
+    \t--name | --path: Glob pattern to match the file name or relative path in 'find'.\n\
+    \t--regex: Regular expression to match the relative path in 'find'.\n\
+    \t--domain: Glob pattern to match the domain in 'find'.\n\
+    \t--min-size | --max-size: Size range to match in 'find', e.g. 10MB.\n\
+    \t--after | --before: Modified date range to match in 'find', as YYYY-MM-DD.\n\
+    \t--extract: Extract the files matched by 'find'.\n\
+    \t--top: Number of top consumers to print in 'stats'. Defaults to 10.\n\
+    \t--dry-run: Print the planned extraction with its size and conflicts without copying anything.\n\
+    \t--resume: Skip the files that were already extracted, based on the size and modified time recorded in the journal of the output directory.\n\
+    \t--sync: Only extract the files that are new or changed since the last extraction.\n\
+    \t--delete: Remove the extracted files that are no longer in the backup, with '--sync'.\n\
+    \t--link: Hard link the extracted files to the backup instead of copying them, for read-only output.\n\
+    \t--progress: Report the extraction progress as a 'bar', 'ndjson' events on stderr, or 'none'.\n\
+    \t--parallel: Extract the selected backups concurrently, sharing the workers between them.\n\
+    \t--max-bandwidth: Maximum rate to copy files at across all workers, e.g. 10MB for 10MB/s.\n\
+    \t--max-iops: Maximum number of file operations per second across all workers.\n\
+    \t--checksum: Hash the extracted files while copying with 'sha256' or 'blake3', and write SHA256SUMS (and B3SUMS) with a checksums.json index.\n\
+    \t--export-plan: Write the planned extraction to a .json or .csv file instead of extracting.\n\
+    \t--plan: Extract the files listed in a .json or .csv plan file, destinations must be within --dst.\n\
     \t--organize: Organize the extracted files by type, size, root, and auto.\n\
+    \t--password: Password for encrypted backups.\n\
+    \t-l | --long: Include the mode, size and modified time in 'ls'.\n\
+    \t--tree: Print the 'ls' output as a tree.\n\
+    \t--depth: Number of levels to descend into with 'ls --tree'.\n\
     \t--workers | --threads: Numbers of workers (threads) to spin up for extraction.\n\
     \t--backup-dir | --source: Custom path for the backup. Defaults to OS specific path.\n\
     \t--output-dir | --destination: Destination directory. Defaults to 'extracted' in current path.\n"
@@ -102,17 +340,44 @@ pub fn arguments(metadata: &constant::MetaData) -> ArgConfig {
 
     let mut version = false;
     let mut list = false;
+    let mut no_size = false;
     let mut all = false;
+    let mut force = false;
     let mut debug = false;
     let mut serial = String::new();
     let mut workers = String::new();
     let mut env_file = String::new();
+    let mut password = String::new();
+    let mut long = false;
+    let mut tree = false;
+    let mut depth = String::new();
+    let mut filters = HashMap::new();
+    let mut extract = false;
+    let mut top = String::new();
+    let mut dry_run = false;
+    let mut resume = false;
+    let mut sync = false;
+    let mut delete = false;
+    let mut link = false;
+    let mut progress = String::new();
+    let mut parallel = false;
+    let mut max_bandwidth = String::new();
+    let mut max_iops = String::new();
+    let mut checksum = String::new();
+    let mut export_plan = String::new();
+    let mut plan_file = String::new();
     let mut backup_dir = String::new();
     let mut output_dir = String::new();
     let mut organize = Organizer::Auto;
+    let mut command_name = String::new();
+    let mut positional = Vec::new();
 
     // Loop through the command-line arguments and parse them.
     let mut i = 1; // Start from the second argument (args[0] is the program name).
+    if args.len() > 1 && !args[1].starts_with('-') {
+        command_name = args[1].clone();
+        i += 1;
+    }
     while i < args.len() {
         match args[i].as_str() {
             "-h" | "--help" | "-H" => {
@@ -125,9 +390,15 @@ pub fn arguments(metadata: &constant::MetaData) -> ArgConfig {
             "--list" => {
                 list = true;
             }
+            "--no-size" => {
+                no_size = true;
+            }
             "--all" => {
                 all = true;
             }
+            "--force" => {
+                force = true;
+            }
             "--debug" => {
                 debug = true;
             }
@@ -155,6 +426,120 @@ pub fn arguments(metadata: &constant::MetaData) -> ArgConfig {
                     missing_value(&args[i - 1]);
                 }
             }
+            "--password" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    password = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "-l" | "--long" => {
+                long = true;
+            }
+            "--tree" => {
+                tree = true;
+            }
+            "--depth" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    depth = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "--name" | "--path" | "--regex" | "--domain" | "--min-size" | "--max-size"
+            | "--after" | "--before" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    filters.insert(args[i - 1].clone(), args[i].clone());
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "--top" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    top = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "--dry-run" => {
+                dry_run = true;
+            }
+            "--export-plan" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    export_plan = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "--plan" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    plan_file = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "--resume" => {
+                resume = true;
+            }
+            "--sync" => {
+                sync = true;
+            }
+            "--delete" => {
+                delete = true;
+            }
+            "--link" => {
+                link = true;
+            }
+            "--progress" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    progress = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            value if value.starts_with("--progress=") => {
+                progress = value.trim_start_matches("--progress=").to_string();
+            }
+            "--parallel" => {
+                parallel = true;
+            }
+            "--max-bandwidth" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    max_bandwidth = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "--checksum" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    checksum = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            value if value.starts_with("--checksum=") => {
+                checksum = value.trim_start_matches("--checksum=").to_string();
+            }
+            "--max-iops" => {
+                i += 1; // Move to the next argument.
+                if i < args.len() {
+                    max_iops = args[i].clone();
+                } else {
+                    missing_value(&args[i - 1]);
+                }
+            }
+            "--extract" => {
+                extract = true;
+            }
             "--organize" => {
                 i += 1; // Move to the next argument.
                 if i < args.len() {
@@ -188,6 +573,9 @@ pub fn arguments(metadata: &constant::MetaData) -> ArgConfig {
                     missing_value(&args[i - 1]);
                 }
             }
+            value if !command_name.is_empty() && !value.starts_with('-') => {
+                positional.push(value.to_string());
+            }
             _ => {
                 println!("\nERROR: Unknown argument: {}\n\n{}", args[i], helper());
                 std::process::exit(1)
@@ -232,23 +620,131 @@ pub fn arguments(metadata: &constant::MetaData) -> ArgConfig {
     if serial.is_empty() {
         serial = squire::env_var("serial", None).unwrap_or_default()
     }
+    let command = if command_name.is_empty() {
+        Command::Extract
+    } else {
+        build_command(&command_name, &positional)
+    };
     let serial_numbers: Vec<String> = serial
         .split(",")
         .filter(|s| !s.is_empty())
         .map(String::from)
         .collect();
+    let depth_final = if depth.is_empty() {
+        None
+    } else {
+        match depth.parse::<usize>() {
+            Ok(value) if value > 0 => Some(value),
+            _ => {
+                println!("ERROR: '--depth' must be a positive number");
+                std::process::exit(1)
+            }
+        }
+    };
+    let query = build_query(&filters);
+    let top_final = if top.is_empty() {
+        10
+    } else {
+        match top.parse::<usize>() {
+            Ok(value) if value > 0 => value,
+            _ => {
+                println!("ERROR: '--top' must be a positive number");
+                std::process::exit(1)
+            }
+        }
+    };
+    let export_plan_final = plan_path("--export-plan", export_plan, false);
+    let plan_final = plan_path("--plan", plan_file, true);
+    if export_plan_final.is_some() && plan_final.is_some() {
+        println!("ERROR: '--export-plan' and '--plan' cannot be used together");
+        std::process::exit(1)
+    }
+    let progress_final = match progress.as_str() {
+        // The bar is only drawn for interactive sessions
+        "" if std::io::stdout().is_terminal() => ProgressMode::Bar,
+        "" => ProgressMode::None,
+        "bar" => ProgressMode::Bar,
+        "ndjson" => ProgressMode::Ndjson,
+        "none" => ProgressMode::None,
+        _ => {
+            println!("ERROR: '--progress' can only be 'bar', 'ndjson' or 'none'");
+            std::process::exit(1)
+        }
+    };
+    let max_bandwidth_final = if max_bandwidth.is_empty() {
+        None
+    } else {
+        // Accepts a rate per second, as a size with an optional '/s' suffix, e.g. 10MB/s
+        match squire::parse_size(max_bandwidth.trim_end_matches("/s")) {
+            Some(value) if value > 0 => Some(value),
+            _ => {
+                println!("ERROR: '--max-bandwidth' must be a positive size per second, e.g. 10MB");
+                std::process::exit(1)
+            }
+        }
+    };
+    let max_iops_final = if max_iops.is_empty() {
+        None
+    } else {
+        match max_iops.parse::<u64>() {
+            Ok(value) if value > 0 => Some(value),
+            _ => {
+                println!("ERROR: '--max-iops' must be a positive number");
+                std::process::exit(1)
+            }
+        }
+    };
+    let checksum_final = match checksum.as_str() {
+        "" => None,
+        "sha256" => Some(checksum::Algorithm::Sha256),
+        "blake3" => Some(checksum::Algorithm::Blake3),
+        _ => {
+            println!("ERROR: '--checksum' can only be 'sha256' or 'blake3'");
+            std::process::exit(1)
+        }
+    };
+    if delete && !sync {
+        println!("ERROR: '--delete' can only be used with '--sync'");
+        std::process::exit(1)
+    }
+    if delete && matches!(command, Command::Find) {
+        println!("ERROR: '--delete' cannot be used with 'find'");
+        std::process::exit(1)
+    }
+    if password.is_empty() {
+        password = squire::env_var("backup_password", Some(vec!["password"])).unwrap_or_default()
+    }
     // todo: implement env var check for all CLI arguments
-    // todo: remove the debug step
-    println!("{:?}", backup_dir_final);
-    println!("{:?}", output_dir_final);
     ArgConfig {
+        command,
         list,
+        no_size,
         all,
+        force,
         debug,
         serial_numbers,
         backup_dir: backup_dir_final,
         output_dir: output_dir_final,
         workers: workers_final,
         organize,
+        password,
+        long,
+        tree,
+        depth: depth_final,
+        query,
+        extract,
+        top: top_final,
+        dry_run,
+        export_plan: export_plan_final,
+        plan: plan_final,
+        resume,
+        sync,
+        delete,
+        link,
+        progress: progress_final,
+        parallel,
+        max_bandwidth: max_bandwidth_final,
+        max_iops: max_iops_final,
+        checksum: checksum_final,
     }
 }
@@ -0,0 +1,203 @@
+use crate::fileio::PlannedFile;
+use crate::verify;
+use std::fs::File;
+use std::path::{Component, Path};
+
+/// Enum for the supported formats of a plan file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanFormat {
+    Json,
+    Csv,
+}
+
+/// Function to get the format of a plan file from its extension
+///
+/// # Arguments
+///
+/// * `path` - The path to the plan file
+///
+/// # Returns
+///
+/// An `Option` containing the `PlanFormat`, if the extension is supported
+pub fn format(path: &Path) -> Option<PlanFormat> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    match extension.as_str() {
+        "json" => Some(PlanFormat::Json),
+        "csv" => Some(PlanFormat::Csv),
+        _ => None,
+    }
+}
+
+/// Function to write the planned files to a JSON or CSV plan file
+///
+/// # Arguments
+///
+/// * `path` - The path to the plan file
+/// * `plan` - The files planned for extraction
+///
+/// # Returns
+///
+/// * `Ok` - If the plan was written successfully
+/// * `Err` - If the function encounters an error
+pub fn write_plan(path: &Path, plan: &[PlannedFile]) -> Result<(), Box<dyn std::error::Error>> {
+    let format = format(path).ok_or(format!(
+        "Plan file '{}' must be either .json or .csv",
+        path.display()
+    ))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = File::create(path)?;
+    match format {
+        PlanFormat::Json => serde_json::to_writer_pretty(file, plan)?,
+        PlanFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            for file in plan {
+                writer.serialize(file)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Function to read the planned files from a JSON or CSV plan file
+///
+/// Every row is checked for a valid fileID, a modified time after the epoch and a destination, use `check_destinations` to confine the destinations.
+///
+/// # Arguments
+///
+/// * `path` - The path to the plan file
+///
+/// # Returns
+///
+/// * `Ok` - A vector of `PlannedFile` structs
+/// * `Err` - If the plan cannot be read or contains an invalid row
+pub fn read_plan(path: &Path) -> Result<Vec<PlannedFile>, Box<dyn std::error::Error>> {
+    let format = format(path).ok_or(format!(
+        "Plan file '{}' must be either .json or .csv",
+        path.display()
+    ))?;
+    let file = File::open(path)?;
+    let plan: Vec<PlannedFile> = match format {
+        PlanFormat::Json => serde_json::from_reader(file)?,
+        PlanFormat::Csv => csv::Reader::from_reader(file)
+            .deserialize()
+            .collect::<Result<_, _>>()?,
+    };
+    for (index, file) in plan.iter().enumerate() {
+        if !verify::is_hex(&file.file_id, 40) {
+            return Err(format!(
+                "Invalid fileID '{}' in row {} of '{}'",
+                file.file_id,
+                index + 1,
+                path.display()
+            )
+            .into());
+        }
+        if file.modified.is_some_and(|modified| modified < 0) {
+            return Err(format!(
+                "Invalid modified time {} in row {} of '{}'",
+                file.modified.unwrap_or_default(),
+                index + 1,
+                path.display()
+            )
+            .into());
+        }
+        if file.destination.as_os_str().is_empty() {
+            return Err(format!(
+                "Missing destination in row {} of '{}'",
+                index + 1,
+                path.display()
+            )
+            .into());
+        }
+    }
+    Ok(plan)
+}
+
+/// Function to check that every planned destination stays within the output directory of the device
+///
+/// A hand-edited plan could otherwise write, or delete with `--sync --delete`, files anywhere on disk.
+///
+/// # Arguments
+///
+/// * `plan` - The planned files of a backup
+/// * `output_path` - The output directory of the device
+///
+/// # Returns
+///
+/// * `Ok` - If every destination is within the output directory
+/// * `Err` - With the first destination that is not
+pub fn check_destinations(plan: &[PlannedFile], output_path: &Path) -> Result<(), String> {
+    for file in plan {
+        let escapes = file
+            .destination
+            .components()
+            .any(|component| component == Component::ParentDir);
+        if escapes || !file.destination.starts_with(output_path) || file.destination == output_path
+        {
+            return Err(format!(
+                "Planned destination '{}' is outside the output directory '{}'",
+                file.destination.display(),
+                output_path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn planned(destination: &str) -> PlannedFile {
+        PlannedFile {
+            serial_number: "F2LXXXXXX01".to_string(),
+            file_id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            domain: "CameraRollDomain".to_string(),
+            relative_path: "Media/DCIM/IMG_0001.JPG".to_string(),
+            size: 0,
+            modified: None,
+            destination: PathBuf::from(destination),
+        }
+    }
+
+    #[test]
+    fn confines_destinations() {
+        let output_path = Path::new("/out/iPhone - F2LXXXXXX01");
+        assert!(check_destinations(
+            &[planned("/out/iPhone - F2LXXXXXX01/Media/IMG_0001.JPG")],
+            output_path
+        )
+        .is_ok());
+        for destination in [
+            "/etc/passwd",
+            "relative/IMG_0001.JPG",
+            "/out/iPhone - F2LXXXXXX01/../other/IMG_0001.JPG",
+            "/out/iPhone - F2LXXXXXX01",
+            "/out/iPhone - F2LXXXXXX012/IMG_0001.JPG",
+        ] {
+            assert!(
+                check_destinations(&[planned(destination)], output_path).is_err(),
+                "{}",
+                destination
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_negative_modified_time() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("plan.json");
+        let mut file = planned("/out/iPhone - F2LXXXXXX01/Media/IMG_0001.JPG");
+        file.modified = Some(1_700_000_000);
+        write_plan(&path, &[file.clone()]).unwrap();
+        assert_eq!(read_plan(&path).unwrap()[0].modified, file.modified);
+
+        file.modified = Some(-1);
+        write_plan(&path, &[file]).unwrap();
+        assert!(read_plan(&path).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::constant::{Backup, ProgressMode};
+use crate::fileio::{ExtractionReport, PlannedFile};
+use crate::observer::ExtractionObserver;
+use crate::squire;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Width of the progress bar in characters
+const BAR_WIDTH: usize = 30;
+/// Minimum interval between two redraws of the progress bar
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Enum for the structured progress events emitted with `--progress ndjson`
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Start {
+        serial_number: &'a str,
+        files: usize,
+        bytes: u64,
+    },
+    File {
+        serial_number: &'a str,
+        domain: &'a str,
+        relative_path: &'a str,
+        destination: &'a str,
+        size: u64,
+        files_done: usize,
+        bytes_done: u64,
+        bytes_per_second: u64,
+        eta_seconds: Option<u64>,
+    },
+    Error {
+        serial_number: &'a str,
+        domain: &'a str,
+        relative_path: &'a str,
+        error: String,
+    },
+    Finish {
+        serial_number: &'a str,
+        #[serde(flatten)]
+        report: &'a ExtractionReport,
+        elapsed_seconds: f64,
+    },
+}
+
+/// Struct to draw the progress bars of concurrent extractions, one line per backup
+#[derive(Debug, Default)]
+pub struct Board {
+    lines: Vec<String>,
+    drawn: usize,
+}
+
+impl Board {
+    /// Function to reserve a line on the board
+    ///
+    /// # Returns
+    ///
+    /// A `usize` with the index of the line
+    fn slot(&mut self) -> usize {
+        self.lines.push(String::new());
+        self.lines.len() - 1
+    }
+
+    /// Function to update a line and redraw the board on stderr
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - The index of the line
+    /// * `line` - The content of the line
+    fn update(&mut self, slot: usize, line: String) {
+        self.lines[slot] = line;
+        let mut stderr = std::io::stderr();
+        if self.drawn > 0 {
+            // Move back to the first line of the board
+            let _ = write!(stderr, "\x1b[{}A", self.drawn);
+        }
+        for line in &self.lines {
+            let _ = writeln!(stderr, "\r\x1b[2K{}", line);
+        }
+        self.drawn = self.lines.len();
+        let _ = stderr.flush();
+    }
+}
+
+/// Struct to track the progress of an extraction by bytes, using the sizes from the manifest
+///
+/// This is the `ExtractionObserver` used by the CLI, drawing a bar or emitting NDJSON events on stderr.
+pub struct Progress {
+    mode: ProgressMode,
+    serial_number: String,
+    total_files: usize,
+    total_bytes: u64,
+    files_done: usize,
+    bytes_done: u64,
+    start: Instant,
+    next_draw: Instant,
+    board: Option<(Arc<Mutex<Board>>, usize)>,
+}
+
+/// Function to format a number of seconds as `MM:SS` or `HH:MM:SS`
+///
+/// # Arguments
+///
+/// * `seconds` - Number of seconds
+///
+/// # Returns
+///
+/// A `String` with the formatted duration
+fn clock(seconds: u64) -> String {
+    match seconds / 3600 {
+        0 => format!("{:02}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{:02}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+impl Progress {
+    /// Function to create a progress tracker
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - How the progress is reported
+    ///
+    /// # Returns
+    ///
+    /// A `Progress` struct
+    pub fn new(mode: ProgressMode) -> Progress {
+        let now = Instant::now();
+        Progress {
+            mode,
+            serial_number: String::new(),
+            total_files: 0,
+            total_bytes: 0,
+            files_done: 0,
+            bytes_done: 0,
+            start: now,
+            next_draw: now,
+            board: None,
+        }
+    }
+
+    /// Function to draw the progress bar as a line of a board shared with other extractions
+    ///
+    /// # Arguments
+    ///
+    /// * `board` - The board shared by the concurrent extractions
+    ///
+    /// # Returns
+    ///
+    /// The `Progress` struct drawing on the board
+    pub fn with_board(mut self, board: Arc<Mutex<Board>>) -> Progress {
+        let slot = board.lock().unwrap().slot();
+        self.board = Some((board, slot));
+        self
+    }
+
+    /// Function to get the throughput since the start of the extraction
+    ///
+    /// # Returns
+    ///
+    /// A `u64` with the bytes copied per second
+    fn throughput(&self) -> u64 {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            (self.bytes_done as f64 / elapsed) as u64
+        } else {
+            0
+        }
+    }
+
+    /// Function to estimate the time left based on the throughput so far
+    ///
+    /// # Returns
+    ///
+    /// An `Option` with the number of seconds left, if any bytes were copied
+    fn eta(&self) -> Option<u64> {
+        match self.throughput() {
+            0 => None,
+            throughput => Some(self.total_bytes.saturating_sub(self.bytes_done) / throughput),
+        }
+    }
+
+    /// Function to write an event as a line of JSON to stderr
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event to be written
+    fn emit(&self, event: &Event) {
+        if self.mode != ProgressMode::Ndjson {
+            return;
+        }
+        match serde_json::to_string(event) {
+            Ok(line) => eprintln!("{}", line),
+            Err(err) => log::warn!("Failed to serialize progress event: {}", err),
+        }
+    }
+
+    /// Function to redraw the progress bar on stderr
+    ///
+    /// # Arguments
+    ///
+    /// * `force` - Flag to redraw even if the last redraw was too recent
+    fn draw(&mut self, force: bool) {
+        let now = Instant::now();
+        if self.mode != ProgressMode::Bar || (!force && now < self.next_draw) {
+            return;
+        }
+        self.next_draw = now + REDRAW_INTERVAL;
+        let ratio = if self.total_bytes > 0 {
+            self.bytes_done as f64 / self.total_bytes as f64
+        } else {
+            self.files_done as f64 / self.total_files.max(1) as f64
+        };
+        let filled = (ratio * BAR_WIDTH as f64) as usize;
+        let eta = self.eta().map_or("?".to_string(), clock);
+        let label = match self.board {
+            Some(_) => self.serial_number.as_str(),
+            None => "Extracting",
+        };
+        let line = format!(
+            "{} {:>3}% |{:<width$}| {}/{} [{}<{}, {}/s] {}/{} files",
+            label,
+            (ratio * 100.0) as usize,
+            "█".repeat(filled.min(BAR_WIDTH)),
+            squire::size_converter(self.bytes_done),
+            squire::size_converter(self.total_bytes),
+            clock(self.start.elapsed().as_secs()),
+            eta,
+            squire::size_converter(self.throughput()),
+            self.files_done,
+            self.total_files,
+            width = BAR_WIDTH
+        );
+        match &self.board {
+            Some((board, slot)) => board.lock().unwrap().update(*slot, line),
+            None => {
+                let mut stderr = std::io::stderr();
+                let _ = write!(stderr, "\r\x1b[2K{}", line);
+                let _ = stderr.flush();
+            }
+        }
+    }
+}
+
+impl ExtractionObserver for Progress {
+    fn on_start(&mut self, backup: &Backup, files: usize, bytes: u64) {
+        self.serial_number = backup.serial_number.clone();
+        self.total_files = files;
+        self.total_bytes = bytes;
+        self.files_done = 0;
+        self.bytes_done = 0;
+        self.start = Instant::now();
+        self.next_draw = self.start;
+        self.emit(&Event::Start {
+            serial_number: &self.serial_number,
+            files: self.total_files,
+            bytes: self.total_bytes,
+        });
+    }
+
+    fn on_file_done(&mut self, file: &PlannedFile) {
+        self.files_done += 1;
+        self.bytes_done += file.size;
+        self.emit(&Event::File {
+            serial_number: &self.serial_number,
+            domain: &file.domain,
+            relative_path: &file.relative_path,
+            destination: &file.destination.to_string_lossy(),
+            size: file.size,
+            files_done: self.files_done,
+            bytes_done: self.bytes_done,
+            bytes_per_second: self.throughput(),
+            eta_seconds: self.eta(),
+        });
+        self.draw(false);
+    }
+
+    fn on_file_error(&mut self, file: &PlannedFile, error: &std::io::Error) {
+        self.files_done += 1;
+        self.emit(&Event::Error {
+            serial_number: &self.serial_number,
+            domain: &file.domain,
+            relative_path: &file.relative_path,
+            error: error.to_string(),
+        });
+        self.draw(false);
+    }
+
+    fn on_finish(&mut self, report: &ExtractionReport) {
+        if self.mode == ProgressMode::Bar {
+            self.draw(true);
+            if self.board.is_none() {
+                eprintln!();
+            }
+        }
+        self.emit(&Event::Finish {
+            serial_number: &self.serial_number,
+            report,
+            elapsed_seconds: self.start.elapsed().as_secs_f64(),
+        });
+    }
+}
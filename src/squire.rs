@@ -1,5 +1,6 @@
 use std::fs::metadata;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{env, fs, thread};
 
@@ -93,16 +94,59 @@ pub fn get_size(path: &Path) -> u64 {
     if path.is_file() {
         metadata(path).map(|meta| meta.len()).unwrap_or(0)
     } else if path.is_dir() {
-        fs::read_dir(path)
-            .unwrap()
-            .flatten()
-            .map(|entry| get_size(&entry.path()))
-            .sum()
+        match fs::read_dir(path) {
+            Ok(entries) => entries.flatten().map(|entry| get_size(&entry.path())).sum(),
+            Err(err) => {
+                log::warn!("Failed to read '{}': {}", path.display(), err);
+                0
+            }
+        }
     } else {
         0
     }
 }
 
+/// Function to get the size of a directory by spreading its entries across multiple threads
+///
+/// # Arguments
+///
+/// * `path` - The path to the directory
+/// * `workers` - Number of threads to use
+///
+/// # Returns
+///
+/// A `u64` containing the size of the directory
+pub fn get_size_parallel(path: &Path, workers: usize) -> u64 {
+    let entries: Vec<PathBuf> = match fs::read_dir(path) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+        Err(err) => {
+            log::warn!("Failed to read '{}': {}", path.display(), err);
+            return 0;
+        }
+    };
+    let index = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut total = 0;
+                    loop {
+                        let current = index.fetch_add(1, Ordering::Relaxed);
+                        match entries.get(current) {
+                            Some(entry) => total += get_size(entry),
+                            None => break total,
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or(0))
+            .sum()
+    })
+}
+
 /// Function to convert byte size to human-readable format
 ///
 /// # Arguments
@@ -125,6 +169,33 @@ pub fn size_converter(byte_size: u64) -> String {
     format!("{:.2} {}", size, size_name[index])
 }
 
+/// Function to convert a human-readable size into bytes
+///
+/// # Arguments
+///
+/// * `size` - The size to convert, e.g. `500`, `10KB`, `1.5 GB`
+///
+/// # Returns
+///
+/// An `Option` containing the size in bytes
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim().to_uppercase();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let exponent = match unit.trim() {
+        "" | "B" => 0,
+        "K" | "KB" => 1,
+        "M" | "MB" => 2,
+        "G" | "GB" => 3,
+        "T" | "TB" => 4,
+        _ => return None,
+    };
+    Some((number * 1024f64.powi(exponent)) as u64)
+}
+
 /// Returns the default number of worker threads (logical cores)
 /// If the number of logical cores cannot be determined, it defaults to 1
 ///
@@ -161,36 +232,39 @@ pub fn get_epoch() -> u64 {
     }
 }
 
+/// File extensions that are considered as media
+pub const MEDIA_EXTENSIONS: [&str; 23] = [
+    "hevc", "h264", "mp4", "m4v", "mov", "avi", "aac", "mp3", "m4a", "alac", "aiff", "wav", "flac",
+    "ac3", "eac3", "heic", "jpg", "jpeg", "png", "gif", "tiff", "bmp", "ico",
+];
+
 /// Returns the media filter for the database query
 ///
 /// # Returns
 ///
 /// A `String` containing the media filter with the supported file extensions
 pub fn media_filter() -> String {
-    "WHERE lower(relativePath) LIKE '%.hevc'
-       OR lower(relativePath) LIKE '%.h264'
-       OR lower(relativePath) LIKE '%.mp4'
-       OR lower(relativePath) LIKE '%.m4v'
-       OR lower(relativePath) LIKE '%.mov'
-       OR lower(relativePath) LIKE '%.avi'
-       OR lower(relativePath) LIKE '%.aac'
-       OR lower(relativePath) LIKE '%.mp3'
-       OR lower(relativePath) LIKE '%.m4a'
-       OR lower(relativePath) LIKE '%.alac'
-       OR lower(relativePath) LIKE '%.aiff'
-       OR lower(relativePath) LIKE '%.wav'
-       OR lower(relativePath) LIKE '%.flac'
-       OR lower(relativePath) LIKE '%.ac3'
-       OR lower(relativePath) LIKE '%.eac3'
-       OR lower(relativePath) LIKE '%.heic'
-       OR lower(relativePath) LIKE '%.jpg'
-       OR lower(relativePath) LIKE '%.jpeg'
-       OR lower(relativePath) LIKE '%.png'
-       OR lower(relativePath) LIKE '%.gif'
-       OR lower(relativePath) LIKE '%.tiff'
-       OR lower(relativePath) LIKE '%.bmp'
-       OR lower(relativePath) LIKE '%.ico';"
-        .to_string()
+    let conditions: Vec<String> = MEDIA_EXTENSIONS
+        .iter()
+        .map(|ext| format!("lower(relativePath) LIKE '%.{}'", ext))
+        .collect();
+    format!("WHERE {};", conditions.join("\n       OR "))
+}
+
+/// Function to check if a file is a media file based on its extension
+///
+/// # Arguments
+///
+/// * `relative_path` - The relative path of the file
+///
+/// # Returns
+///
+/// A `bool` indicating if the file has one of the media extensions
+pub fn is_media(relative_path: &str) -> bool {
+    let lower = relative_path.to_lowercase();
+    MEDIA_EXTENSIONS
+        .iter()
+        .any(|ext| lower.ends_with(&format!(".{}", ext)))
 }
 
 /// Function to get the file type
@@ -245,14 +319,14 @@ fn classify_size(byte_size: usize) -> String {
 ///
 /// # Arguments
 ///
-/// * `src_path` - The path to the file or directory
+/// * `byte_size` - The size of the file in bytes
 /// * `filename` - Name of the file
 ///
 /// # Returns
 ///
 /// A `String` with human-readable format of the file/directory size
-pub fn file_size(src_path: &Path, filename: &String) -> PathBuf {
-    PathBuf::from(&classify_size(get_size(src_path) as usize))
+pub fn file_size(byte_size: u64, filename: &String) -> PathBuf {
+    PathBuf::from(&classify_size(byte_size as usize))
         .join(filename)
         .to_owned()
 }
@@ -285,3 +359,29 @@ pub fn env_var(env_key: &'static str, alias: Option<Vec<&'static str>>) -> Optio
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(parse_size("500"), Some(500));
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("10KB"), Some(10 * 1024));
+        assert_eq!(parse_size("10 kb"), Some(10 * 1024));
+        assert_eq!(parse_size("1.5 GB"), Some(1536 * 1024 * 1024));
+        assert_eq!(parse_size("  2m  "), Some(2 * 1024 * 1024));
+        assert_eq!(parse_size("1T"), Some(1 << 40));
+        assert_eq!(parse_size("1.5B"), Some(1));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        for size in [
+            "", "KB", "-1", "1.2.3", "10 XB", "10KBB", "1e3", "NaN", "inf",
+        ] {
+            assert_eq!(parse_size(size), None, "{}", size);
+        }
+    }
+}
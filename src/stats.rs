@@ -0,0 +1,174 @@
+use crate::manifest::Manifest;
+use crate::{constant, squire};
+use std::collections::HashMap;
+
+/// Struct to store the number of files and bytes used by a group
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Struct to store the disk usage of a backup broken down by domain, app, extension and category
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub total: Usage,
+    pub domains: HashMap<String, Usage>,
+    pub apps: HashMap<String, Usage>,
+    pub extensions: HashMap<String, Usage>,
+    pub categories: HashMap<String, Usage>,
+}
+
+/// Function to resolve the bundle ID of an app from its domain
+///
+/// # Arguments
+///
+/// * `domain` - The domain of a file, e.g. `AppDomain-com.example.app`
+///
+/// # Returns
+///
+/// An `Option` containing the bundle ID, or `None` if the domain does not belong to an app
+pub fn app_bundle(domain: &str) -> Option<&str> {
+    ["AppDomainGroup-", "AppDomainPlugin-", "AppDomain-"]
+        .iter()
+        .find_map(|prefix| domain.strip_prefix(prefix))
+}
+
+/// Function to classify a file into a media category based on its extension
+///
+/// # Arguments
+///
+/// * `extension` - The lowercase extension of the file
+///
+/// # Returns
+///
+/// A `&str` with the category name
+pub fn category(extension: &str) -> &'static str {
+    match extension {
+        "heic" | "jpg" | "jpeg" | "png" | "gif" | "tiff" | "bmp" | "ico" => "Photos",
+        "hevc" | "h264" | "mp4" | "m4v" | "mov" | "avi" => "Videos",
+        "aac" | "mp3" | "m4a" | "alac" | "aiff" | "wav" | "flac" | "ac3" | "eac3" => "Audio",
+        "db" | "sqlite" | "sqlitedb" | "db-wal" | "db-shm" => "Databases",
+        "plist" => "Property Lists",
+        _ => "Other",
+    }
+}
+
+/// Function to aggregate the disk usage of a backup from the file metadata in its manifest
+///
+/// # Arguments
+///
+/// * `backup` - The backup information, unlocked with `Backup::unlock` if it is encrypted
+///
+/// # Returns
+///
+/// * `Ok` - The `Stats` of the backup
+/// * `Err` - If the manifest cannot be read
+pub fn collect(backup: &constant::Backup) -> Result<Stats, Box<dyn std::error::Error>> {
+    let manifest = Manifest::from_backup(backup)?;
+    let mut stats = Stats::default();
+    for entry in manifest.entries().files_only() {
+        let entry = entry?;
+        let size = entry.size();
+        let name = entry.relative_path.rsplit('/').next().unwrap_or_default();
+        let extension = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => extension.to_lowercase(),
+            _ => String::new(),
+        };
+        stats.total.add(size);
+        stats
+            .domains
+            .entry(entry.domain.clone())
+            .or_default()
+            .add(size);
+        if let Some(bundle) = app_bundle(&entry.domain) {
+            stats.apps.entry(bundle.to_string()).or_default().add(size);
+        }
+        stats
+            .categories
+            .entry(category(&extension).to_string())
+            .or_default()
+            .add(size);
+        let extension = if extension.is_empty() {
+            "(none)".to_string()
+        } else {
+            extension
+        };
+        stats.extensions.entry(extension).or_default().add(size);
+    }
+    Ok(stats)
+}
+
+/// Function to print the top consumers of a group
+///
+/// # Arguments
+///
+/// * `title` - The title of the group
+/// * `group` - The usage of each member in the group
+/// * `total` - The total number of bytes in the backup
+/// * `top` - Number of members to print
+fn print_group(title: &str, group: &HashMap<String, Usage>, total: u64, top: usize) {
+    let mut members: Vec<(&String, &Usage)> = group.iter().collect();
+    members.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+    let width = members
+        .iter()
+        .take(top)
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default()
+        .max(title.len());
+    println!(
+        "\n{:<width$}  {:>10}  {:>12}  {:>7}",
+        title,
+        "Files",
+        "Size",
+        "Share",
+        width = width
+    );
+    println!("{:-<1$}", "", width + 35);
+    for (name, usage) in members.iter().take(top) {
+        let share = if total == 0 {
+            0.0
+        } else {
+            usage.bytes as f64 * 100.0 / total as f64
+        };
+        println!(
+            "{:<width$}  {:>10}  {:>12}  {:>6.2}%",
+            name,
+            usage.files,
+            squire::size_converter(usage.bytes),
+            share,
+            width = width
+        );
+    }
+    if members.len() > top {
+        println!("... and {} more", members.len() - top);
+    }
+}
+
+/// Function to print the disk usage of a backup
+///
+/// # Arguments
+///
+/// * `stats` - The `Stats` of the backup
+/// * `top` - Number of top consumers to print for each group
+pub fn print_stats(stats: &Stats, top: usize) {
+    println!(
+        "\nTotal: {} files, {}",
+        stats.total.files,
+        squire::size_converter(stats.total.bytes)
+    );
+    let total = stats.total.bytes;
+    print_group("Category", &stats.categories, total, top);
+    print_group("Domain", &stats.domains, total, top);
+    print_group("App", &stats.apps, total, top);
+    print_group("Extension", &stats.extensions, total, top);
+    println!();
+}
@@ -0,0 +1,229 @@
+use crate::cancel::CancellationToken;
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest sleep between two checks for cancellation while throttled
+const SLEEP_SLICE: Duration = Duration::from_millis(100);
+
+/// Struct to hold the state of a token bucket
+///
+/// The bucket refills at a fixed rate up to a burst of one second.
+/// Takes are allowed to overdraw the bucket, and the caller sleeps until the debt is paid off,
+/// so concurrent callers are served in the order they arrive.
+struct Bucket {
+    rate: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    /// Function to create a bucket that is full
+    ///
+    /// # Arguments
+    ///
+    /// * `rate` - Number of tokens added per second
+    ///
+    /// # Returns
+    ///
+    /// A `Bucket` struct
+    fn new(rate: u64) -> Bucket {
+        Bucket {
+            rate: rate as f64,
+            state: Mutex::new((rate as f64, Instant::now())),
+        }
+    }
+
+    /// Function to take tokens from the bucket
+    ///
+    /// # Arguments
+    ///
+    /// * `amount` - Number of tokens to take
+    ///
+    /// # Returns
+    ///
+    /// A `Duration` to wait for before the tokens are available
+    fn take(&self, amount: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, refilled) = &mut *state;
+        let now = Instant::now();
+        *tokens =
+            (*tokens + now.duration_since(*refilled).as_secs_f64() * self.rate).min(self.rate);
+        *refilled = now;
+        *tokens -= amount as f64;
+        if *tokens < 0.0 {
+            Duration::from_secs_f64(-*tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Struct to limit the bandwidth and the I/O operations of an extraction
+///
+/// A single throttle is shared by all the workers, and by all the backups extracted with `--parallel`,
+/// so the limits apply to the process as a whole. An operation is opening a file or copying a chunk of it.
+#[derive(Default)]
+pub struct Throttle {
+    bandwidth: Option<Bucket>,
+    iops: Option<Bucket>,
+}
+
+/// Function to sleep for a duration, waking up early if the extraction is cancelled
+///
+/// # Arguments
+///
+/// * `duration` - The duration to sleep for
+/// * `cancel` - The token checked while sleeping
+///
+/// # Returns
+///
+/// * `Ok` - Once the duration has passed
+/// * `Err` - `Interrupted` if the extraction was cancelled
+fn wait(duration: Duration, cancel: &CancellationToken) -> io::Result<()> {
+    let deadline = Instant::now() + duration;
+    loop {
+        cancel.check()?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        thread::sleep((deadline - now).min(SLEEP_SLICE));
+    }
+}
+
+impl Throttle {
+    /// Function to create a throttle
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bandwidth` - Maximum number of bytes copied per second, if limited
+    /// * `max_iops` - Maximum number of I/O operations per second, if limited
+    ///
+    /// # Returns
+    ///
+    /// A `Throttle` struct
+    pub fn new(max_bandwidth: Option<u64>, max_iops: Option<u64>) -> Throttle {
+        Throttle {
+            bandwidth: max_bandwidth.filter(|rate| *rate > 0).map(Bucket::new),
+            iops: max_iops.filter(|rate| *rate > 0).map(Bucket::new),
+        }
+    }
+
+    /// Function to get the size of the chunks to copy a file in
+    ///
+    /// Large chunks are split when the bandwidth is limited, so the copy is spread evenly over time.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - Size of the chunks when the bandwidth is not limited
+    ///
+    /// # Returns
+    ///
+    /// A `usize` with the size of the chunks in bytes
+    pub fn chunk_size(&self, max: usize) -> usize {
+        match &self.bandwidth {
+            Some(bucket) => ((bucket.rate / 10.0) as usize).clamp(64 * 1024, max.max(64 * 1024)),
+            None => max,
+        }
+    }
+
+    /// Function to account for an I/O operation, waiting if the limit is reached
+    ///
+    /// # Arguments
+    ///
+    /// * `cancel` - The token checked while waiting
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - Once the operation is allowed
+    /// * `Err` - `Interrupted` if the extraction was cancelled
+    pub fn operation(&self, cancel: &CancellationToken) -> io::Result<()> {
+        match &self.iops {
+            Some(bucket) => wait(bucket.take(1), cancel),
+            None => Ok(()),
+        }
+    }
+
+    /// Function to account for the bytes copied, waiting if the limit is reached
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Number of bytes copied
+    /// * `cancel` - The token checked while waiting
+    ///
+    /// # Returns
+    ///
+    /// * `Ok` - Once the bytes are within the limit
+    /// * `Err` - `Interrupted` if the extraction was cancelled
+    pub fn transferred(&self, bytes: u64, cancel: &CancellationToken) -> io::Result<()> {
+        match &self.bandwidth {
+            Some(bucket) => wait(bucket.take(bytes), cancel),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Function to pretend the bucket was last refilled some time ago
+    fn rewind(bucket: &Bucket, tokens: f64, elapsed: Duration) {
+        *bucket.state.lock().unwrap() = (tokens, Instant::now() - elapsed);
+    }
+
+    #[test]
+    fn bucket_starts_full() {
+        let bucket = Bucket::new(1000);
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        let wait = bucket.take(500);
+        assert!(wait > Duration::from_millis(490) && wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn debt_is_paid_off_in_order() {
+        let bucket = Bucket::new(100);
+        rewind(&bucket, 0.0, Duration::ZERO);
+        let first = bucket.take(50);
+        let second = bucket.take(50);
+        assert!(first > Duration::from_millis(490) && first <= Duration::from_millis(500));
+        assert!(second > Duration::from_millis(990) && second <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn refill_is_capped_at_one_second() {
+        let bucket = Bucket::new(100);
+        rewind(&bucket, -50.0, Duration::from_secs(10));
+        assert_eq!(bucket.take(100), Duration::ZERO);
+        assert!(bucket.take(1) > Duration::ZERO);
+    }
+
+    #[test]
+    fn chunk_size_follows_bandwidth() {
+        assert_eq!(Throttle::new(None, None).chunk_size(1 << 20), 1 << 20);
+        assert_eq!(Throttle::new(Some(1), None).chunk_size(1 << 20), 64 * 1024);
+        assert_eq!(
+            Throttle::new(Some(10 << 20), None).chunk_size(8 << 20),
+            1 << 20
+        );
+        assert_eq!(
+            Throttle::new(Some(1 << 30), None).chunk_size(1 << 20),
+            1 << 20
+        );
+        assert_eq!(Throttle::new(Some(1), None).chunk_size(1024), 64 * 1024);
+        assert!(Throttle::new(Some(0), Some(0)).bandwidth.is_none());
+    }
+
+    #[test]
+    fn cancellation_interrupts_the_wait() {
+        let throttle = Throttle::new(None, Some(1));
+        let cancel = CancellationToken::new();
+        throttle.operation(&cancel).unwrap();
+        cancel.cancel();
+        let started = Instant::now();
+        let err = throttle.operation(&cancel).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < SLEEP_SLICE);
+    }
+}
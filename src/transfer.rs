@@ -0,0 +1,339 @@
+use crate::cancel::CancellationToken;
+use crate::checksum::{Algorithm, Digests, Hasher};
+use crate::throttle::Throttle;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Flag set once the kernel or filesystem lacks reflinks, so it is not attempted for every file
+#[cfg(target_os = "linux")]
+static REFLINK_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+/// Flag set once the kernel lacks `copy_file_range`, so it is not attempted for every file
+#[cfg(target_os = "linux")]
+static COPY_RANGE_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Enum for the methods a file can be copied with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Hard link to the blob in the backup (`--link`)
+    Hardlink,
+    /// Copy-on-write clone of the blob (`FICLONE`), on btrfs, XFS and similar
+    Reflink,
+    /// Copy within the kernel (`copy_file_range`)
+    CopyRange,
+    /// Copy through userspace, which also computes the hashes
+    Userspace,
+}
+
+/// Size of the chunks copied between two checks for cancellation
+#[cfg(target_os = "linux")]
+const CHUNK_SIZE: usize = 64 * 1024 * 1024;
+/// Size of the buffer used to copy through userspace
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// Function to check if an error means the operation is not supported for the given files
+///
+/// # Arguments
+///
+/// * `err` - The error returned by the kernel
+///
+/// # Returns
+///
+/// A `bool` indicating if the copy should fall back to the next method
+#[cfg(target_os = "linux")]
+fn is_unsupported(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EXDEV)
+            | Some(libc::ENOSYS)
+            | Some(libc::EOPNOTSUPP)
+            | Some(libc::EINVAL)
+            | Some(libc::ENOTTY)
+    )
+}
+
+/// Function to check if an error means the operation is not supported for any file
+///
+/// Errors such as `EXDEV` only apply to the given pair of files, e.g. a backup on another filesystem,
+/// so the operation is still attempted for the next ones.
+///
+/// # Arguments
+///
+/// * `err` - The error returned by the kernel
+///
+/// # Returns
+///
+/// A `bool` indicating if the operation should no longer be attempted
+#[cfg(target_os = "linux")]
+fn is_unsupported_everywhere(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY)
+    )
+}
+
+/// Function to clone a file with copy-on-write, sharing the extents of the source
+///
+/// # Arguments
+///
+/// * `src` - The source file
+/// * `dest` - The empty destination file
+///
+/// # Returns
+///
+/// * `Ok` - If the file was cloned
+/// * `Err` - If the filesystem does not support reflinks
+#[cfg(target_os = "linux")]
+fn reflink(src: &File, dest: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // _IOW(0x94, 9, int) from linux/fs.h
+    const FICLONE: libc::c_ulong = 0x40049409;
+    let result = unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE as _, src.as_raw_fd()) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Function to copy a file within the kernel, without passing the data through userspace
+///
+/// # Arguments
+///
+/// * `src` - The source file
+/// * `dest` - The destination file
+/// * `cancel` - The token checked between chunks
+/// * `throttle` - The limits applied to each chunk
+///
+/// # Returns
+///
+/// * `Ok` - Number of bytes copied
+/// * `Err` - If the kernel cannot copy between the files
+#[cfg(target_os = "linux")]
+fn copy_range(
+    src: &File,
+    dest: &File,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+    let chunk_size = throttle.chunk_size(CHUNK_SIZE);
+    let mut copied = 0;
+    loop {
+        cancel.check()?;
+        throttle.operation(cancel)?;
+        let result = unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                std::ptr::null_mut(),
+                dest.as_raw_fd(),
+                std::ptr::null_mut(),
+                chunk_size,
+                0,
+            )
+        };
+        match result {
+            0 => return Ok(copied),
+            n if n > 0 => {
+                copied += n as u64;
+                throttle.transferred(n as u64, cancel)?;
+            }
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+/// Function to copy a file with a reflink, or within the kernel if reflinks are not supported
+///
+/// # Arguments
+///
+/// * `src` - The source file
+/// * `dest` - The empty destination file
+/// * `cancel` - The token checked while copying
+/// * `throttle` - The limits applied to the copy
+///
+/// # Returns
+///
+/// * `Ok` - The method used, or `None` if neither is supported and the data is yet to be copied
+/// * `Err` - If the function encounters an error
+#[cfg(target_os = "linux")]
+fn kernel_copy(
+    src: &File,
+    dest: &File,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> io::Result<Option<Method>> {
+    if !REFLINK_UNSUPPORTED.load(Ordering::Relaxed) {
+        match reflink(src, dest) {
+            Ok(_) => return Ok(Some(Method::Reflink)),
+            Err(err) if is_unsupported(&err) => {
+                log::debug!("Reflink is not supported, falling back: {}", err);
+                if is_unsupported_everywhere(&err) {
+                    REFLINK_UNSUPPORTED.store(true, Ordering::Relaxed);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if !COPY_RANGE_UNSUPPORTED.load(Ordering::Relaxed) {
+        match copy_range(src, dest, cancel, throttle) {
+            Ok(_) => return Ok(Some(Method::CopyRange)),
+            // Nothing is copied when the call is rejected, so the offsets are still at the start
+            Err(err) if is_unsupported(&err) => {
+                log::debug!("copy_file_range is not supported, falling back: {}", err);
+                if is_unsupported_everywhere(&err) {
+                    COPY_RANGE_UNSUPPORTED.store(true, Ordering::Relaxed);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(None)
+}
+
+/// Function to copy the data of a file through userspace, computing its hashes
+///
+/// # Arguments
+///
+/// * `src` - The source file
+/// * `dest` - The destination, or a sink to only hash the source
+/// * `algorithm` - The algorithm to hash with, or `None` for SHA-256 only
+/// * `cancel` - The token checked between chunks
+/// * `throttle` - The limits applied to each chunk
+///
+/// # Returns
+///
+/// * `Ok` - The `Digests` of the data
+/// * `Err` - If the function encounters an error
+fn hashed_copy(
+    src: &mut File,
+    dest: &mut impl Write,
+    algorithm: Option<Algorithm>,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> io::Result<Digests> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; throttle.chunk_size(BUFFER_SIZE)];
+    loop {
+        cancel.check()?;
+        throttle.operation(cancel)?;
+        let read = match src.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        throttle.transferred(read as u64, cancel)?;
+        dest.write_all(&buffer[..read])?;
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Function to copy the data of a file with the fastest method available
+///
+/// The kernel copies are skipped when an algorithm is given, so the data is hashed while it is copied.
+///
+/// # Arguments
+///
+/// * `src` - The source file
+/// * `dest` - The empty destination file
+/// * `algorithm` - The algorithm the copy must be hashed with, if any
+/// * `cancel` - The token checked while copying
+/// * `throttle` - The limits applied to the copy
+///
+/// # Returns
+///
+/// * `Ok` - The method used, and the hashes of the data if it was copied through userspace
+/// * `Err` - If the function encounters an error
+fn copy_data(
+    src: &mut File,
+    dest: &mut File,
+    algorithm: Option<Algorithm>,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> io::Result<(Method, Option<Digests>)> {
+    #[cfg(target_os = "linux")]
+    if algorithm.is_none() {
+        if let Some(method) = kernel_copy(src, dest, cancel, throttle)? {
+            return Ok((method, None));
+        }
+    }
+    let digests = hashed_copy(src, dest, algorithm, cancel, throttle)?;
+    Ok((Method::Userspace, Some(digests)))
+}
+
+/// Function to copy or link a blob from the backup to a new file
+///
+/// # Arguments
+///
+/// * `src_path` - The path to the blob in the backup directory
+/// * `dest_path` - The path of the new file
+/// * `link` - Flag to hard link the blob instead of copying it
+/// * `algorithm` - The algorithm the file must be hashed with, if any
+/// * `cancel` - The token checked while copying large files
+/// * `throttle` - The bandwidth and I/O limits shared with the other workers
+///
+/// # Returns
+///
+/// * `Ok` - The method used, and the hashes of the data if it was read through userspace
+/// * `Err` - If the function encounters an error, or `Interrupted` if the copy was cancelled
+pub fn copy_file(
+    src_path: &Path,
+    dest_path: &Path,
+    link: bool,
+    algorithm: Option<Algorithm>,
+    cancel: &CancellationToken,
+    throttle: &Throttle,
+) -> io::Result<(Method, Option<Digests>)> {
+    // Linking or opening the blob counts as an operation on its own
+    throttle.operation(cancel)?;
+    if link {
+        match std::fs::hard_link(src_path, dest_path) {
+            Ok(_) if algorithm.is_none() => return Ok((Method::Hardlink, None)),
+            Ok(_) => {
+                // The link shares the data of the blob, so the blob is read to hash it
+                let mut src_file = File::open(src_path)?;
+                let digests =
+                    hashed_copy(&mut src_file, &mut io::sink(), algorithm, cancel, throttle)?;
+                return Ok((Method::Hardlink, Some(digests)));
+            }
+            Err(err) => log::debug!(
+                "Failed to link '{}', copying instead: {}",
+                src_path.display(),
+                err
+            ),
+        }
+    }
+    let mut src_file = File::open(src_path)?;
+    let mut dest_file = File::create(dest_path)?;
+    copy_data(&mut src_file, &mut dest_file, algorithm, cancel, throttle)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latches_only_on_global_errors() {
+        let exdev = io::Error::from_raw_os_error(libc::EXDEV);
+        assert!(is_unsupported(&exdev));
+        assert!(!is_unsupported_everywhere(&exdev));
+        let einval = io::Error::from_raw_os_error(libc::EINVAL);
+        assert!(is_unsupported(&einval));
+        assert!(!is_unsupported_everywhere(&einval));
+        let enosys = io::Error::from_raw_os_error(libc::ENOSYS);
+        assert!(is_unsupported(&enosys));
+        assert!(is_unsupported_everywhere(&enosys));
+        let enospc = io::Error::from_raw_os_error(libc::ENOSPC);
+        assert!(!is_unsupported(&enospc));
+        assert!(!is_unsupported_everywhere(&enospc));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::constant;
+use crate::manifest::Manifest;
+use std::collections::HashSet;
+use std::fs::read_dir;
+use std::path::PathBuf;
+
+/// Struct to store the outcome of verifying a backup against its manifest
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub checked: u64,
+    pub missing: Vec<String>,
+    pub size_mismatch: Vec<(String, u64, u64)>,
+    pub undecodable: Vec<String>,
+    pub orphans: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no issues were found
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+            && self.size_mismatch.is_empty()
+            && self.undecodable.is_empty()
+            && self.orphans.is_empty()
+    }
+}
+
+/// Function to compute the size of the blob that is stored on disk
+///
+/// Encrypted backups store files with AES-CBC and PKCS#7 padding, so the blob is always
+/// rounded up to the next 16 byte boundary.
+///
+/// # Arguments
+///
+/// * `size` - The size of the file as recorded in the manifest
+/// * `encrypted` - Boolean flag to indicate if the backup is encrypted
+///
+/// # Returns
+///
+/// A `u64` with the expected blob size
+fn expected_blob_size(size: u64, encrypted: bool) -> u64 {
+    if encrypted {
+        (size / 16 + 1) * 16
+    } else {
+        size
+    }
+}
+
+/// Function to check if a file name is a hex string of the given length
+///
+/// # Arguments
+///
+/// * `name` - The file name
+/// * `length` - The expected length
+///
+/// # Returns
+///
+/// A `bool` indicating if the name is a hex string of the given length
+pub fn is_hex(name: &str, length: usize) -> bool {
+    name.len() == length && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Function to verify the integrity of a backup against its manifest database
+///
+/// Every file in the manifest is checked for its hashed blob (`<id[..2]>/<id>`, or `<id>` for legacy backups) and its size,
+/// and every blob on disk is checked for a matching manifest entry.
+///
+/// # Arguments
+///
+/// * `backup` - The backup information, unlocked with `Backup::unlock` if it is encrypted
+///
+/// # Returns
+///
+/// * `Ok` - A `VerifyReport` with the issues found
+/// * `Err` - If the manifest database cannot be read
+pub fn verify_backup(
+    backup: &constant::Backup,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let mut report = VerifyReport::default();
+    let mut known = HashSet::new();
+
+    let manifest = Manifest::from_backup(backup)?;
+    for entry in manifest.entries().files_only() {
+        let entry = entry?;
+        let file_id = entry.file_id;
+        let expected = entry.metadata.map(|meta| meta.size);
+        report.checked += 1;
+        if expected.is_none() {
+            report.undecodable.push(file_id.clone());
+        }
+        match backup.blob_path(&file_id).and_then(|path| path.metadata()) {
+            Ok(meta) => {
+                if let Some(size) = expected {
+                    let expected = expected_blob_size(size, backup.encrypted);
+                    if meta.len() != expected {
+                        report
+                            .size_mismatch
+                            .push((file_id.clone(), expected, meta.len()));
+                    }
+                }
+            }
+            // Empty files are not always stored as blobs
+            Err(_) if expected == Some(0) => (),
+            Err(_) => report.missing.push(file_id.clone()),
+        }
+        known.insert(file_id);
+    }
+
+    if backup.manifest_format() == Some(constant::ManifestFormat::Mbdb) {
+        // Legacy backups store the blobs flat in the backup directory
+        for entry in read_dir(&backup.path)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if is_hex(&name, 40) && !known.contains(&name) {
+                report.orphans.push(entry.path());
+            }
+        }
+        return Ok(report);
+    }
+    for entry in read_dir(&backup.path)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !is_hex(&name, 2) || !entry.path().is_dir() {
+            continue;
+        }
+        for blob in read_dir(entry.path())?.flatten() {
+            if !known.contains(&blob.file_name().to_string_lossy().to_string()) {
+                report.orphans.push(blob.path());
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Function to print the verification report
+///
+/// # Arguments
+///
+/// * `report` - The verification report of a backup
+pub fn print_report(report: &VerifyReport) {
+    for file_id in &report.missing {
+        println!("MISSING     {}", file_id);
+    }
+    for (file_id, expected, actual) in &report.size_mismatch {
+        println!(
+            "SIZE        {} (expected {} bytes, found {} bytes)",
+            file_id, expected, actual
+        );
+    }
+    for file_id in &report.undecodable {
+        println!("METADATA    {} (unable to decode file metadata)", file_id);
+    }
+    for orphan in &report.orphans {
+        println!("ORPHAN      {}", orphan.display());
+    }
+    println!();
+    println!("{:<20} {}", "Files checked", report.checked);
+    println!("{:<20} {}", "Missing blobs", report.missing.len());
+    println!("{:<20} {}", "Size mismatches", report.size_mismatch.len());
+    println!("{:<20} {}", "Unreadable metadata", report.undecodable.len());
+    println!("{:<20} {}", "Orphan blobs", report.orphans.len());
+}